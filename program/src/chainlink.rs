@@ -0,0 +1,35 @@
+//! reads chainlink price feeds through the `chainlink_solana` client, which queries the
+//! chainlink store program by CPI. the store program is passed in as an account and has
+//! to be the one the client was built for, any other program could answer anything
+
+use chainlink_solana as client;
+use solana_program::{ account_info::AccountInfo, msg, program_error::ProgramError };
+use std::convert::TryFrom;
+
+pub use client::ID;
+
+/// rejects a `store_program` other than the chainlink store
+pub fn check_program(store_program: &AccountInfo) -> Result<(), ProgramError> {
+    if store_program.key != &ID {
+        msg!("Program {} is not the chainlink store {}", store_program.key, ID);
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// latest answer of the feed, `None` before its first round or if the answer is negative,
+/// which a price can't be
+pub fn get_price<'a>(store_program: &AccountInfo<'a>, feed_account: &AccountInfo<'a>) -> Result<Option<u128>, ProgramError> {
+    check_program(store_program)?;
+    let round = client::latest_round_data(store_program.clone(), feed_account.clone())?;
+    if round.round_id == 0 {
+        return Ok(None);
+    }
+    Ok(u128::try_from(round.answer).ok())
+}
+
+/// decimals of the feed's answers
+pub fn get_decimals<'a>(store_program: &AccountInfo<'a>, feed_account: &AccountInfo<'a>) -> Result<u8, ProgramError> {
+    check_program(store_program)?;
+    client::decimals(store_program.clone(), feed_account.clone())
+}
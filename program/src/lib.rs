@@ -1,21 +1,35 @@
-use borsh::{ BorshDeserialize, BorshSerialize };
+use borsh::{ BorshDeserialize, BorshSchema, BorshSerialize };
 use solana_program::{
     log::sol_log_compute_units,
     account_info::{ next_account_info, AccountInfo },
-    entrypoint,
-    entrypoint::ProgramResult,
+    entrypoint::{ ProgramResult, MAX_PERMITTED_DATA_INCREASE },
     msg,
+    program::{ invoke, set_return_data },
     program_error::ProgramError,
     pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::{ clock::Clock, rent::Rent },
 };
-use std::io::ErrorKind::InvalidData;
+use std::{ convert::TryFrom, io::ErrorKind::InvalidData, ops::Range };
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, TokenAccount, Transfer, MintTo};
+use anchor_spl::token::{self, Burn, Mint, Token, TokenAccount, Transfer, MintTo};
 use anchor_lang::solana_program::program_option::COption;
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+use swap_client::fees::Fees;
+use swap_math::{ bn::U192, decimal::{ Decimal, SignedDecimal }, math::FeeCalculator };
+
+mod chainlink;
+
+declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug)]
 pub struct ChatMessage {
     pub archive_id: String,
-    pub created_on: String
+    pub created_on: String,
+    /// account that signed the write, set by `process_instruction`
+    pub sender: Pubkey,
+    /// short message stored on chain instead of (or next to) the arweave tx,
+    /// at most `MAX_INLINE_TEXT_LEN` bytes
+    pub inline_text: Option<String>
 }
 
 // example arweave tx (length 43)
@@ -23,81 +37,750 @@ pub struct ChatMessage {
 // ReUohI9tEmXQ6EN9H9IkRjY9bSdgql_OdLUCOeMEte0
 const DUMMY_TX_ID: &str = "0000000000000000000000000000000000000000000";
 const DUMMY_CREATED_ON: &str = "0000000000000000"; // milliseconds, 16 digits
+pub const CHAT_MESSAGE_SLOTS: usize = 20;
+pub const MAX_INLINE_TEXT_LEN: usize = 256;
+/// serialized size of a slot holding an arweave tx id and the longest inline text
+pub const MAX_CHAT_MESSAGE_LEN: usize =
+    4 + DUMMY_TX_ID.len() + 4 + DUMMY_CREATED_ON.len() + 32 + 1 + 4 + MAX_INLINE_TEXT_LEN;
+/// account size needed to fill every slot with inline text, followed by the nonce. smaller
+/// accounts still work, writes fail with `ChatError::AccountTooSmall` once the messages no longer fit
+pub const CHAT_MESSAGES_ACCOUNT_LEN: usize = 4 + CHAT_MESSAGE_SLOTS * MAX_CHAT_MESSAGE_LEN + 8;
+/// serialized size of a slot with a full length archive_id and created_on and no inline
+/// text, like the dummy slots. only such slots can be overwritten in place, see `slot_byte_range`
+pub const FIXED_CHAT_MESSAGE_LEN: usize = 4 + DUMMY_TX_ID.len() + 4 + DUMMY_CREATED_ON.len() + 32 + 1;
+/// serialized size of the smallest slot, with empty strings and no inline text
+const MIN_CHAT_MESSAGE_LEN: usize = 4 + 4 + 32 + 1;
 pub fn get_init_chat_message() -> ChatMessage {
-    ChatMessage{ archive_id: String::from(DUMMY_TX_ID), created_on: String::from(DUMMY_CREATED_ON) }
+    ChatMessage{ archive_id: String::from(DUMMY_TX_ID), created_on: String::from(DUMMY_CREATED_ON), sender: Pubkey::default(), inline_text: None }
 }
 pub fn get_init_chat_messages() -> Vec<ChatMessage> {
     let mut messages = Vec::new();
-    for _ in 0..20 {
-        messages.push(get_init_chat_message());
-    }
+    write_init_messages_into(&mut messages, CHAT_MESSAGE_SLOTS);
     return messages;
 }
 
-entrypoint!(process_instruction);
+/// appends `n` dummy messages to `buf`, reserving the room for all of them up front
+/// so filling never reallocates
+pub fn write_init_messages_into(buf: &mut Vec<ChatMessage>, n: usize) {
+    buf.reserve_exact(n);
+    for _ in 0..n {
+        buf.push(get_init_chat_message());
+    }
+}
+
+/// borsh schema of the account data layout (`Vec<ChatMessage>`), serialized with borsh
+/// so clients can generate their layouts from it
+pub fn chat_messages_schema() -> Vec<u8> {
+    <Vec<ChatMessage>>::schema_container()
+        .try_to_vec()
+        .expect("Failed to encode schema.")
+}
+
+/// a slot is empty when it has no inline text and its archive_id, ignoring surrounding
+/// whitespace, is empty or only zeros (`DUMMY_TX_ID` and any truncated form of it).
+/// real arweave tx ids are 43 base64url characters and never all zeros.
+pub fn is_dummy_slot(msg: &ChatMessage) -> bool {
+    msg.inline_text.is_none() && msg.archive_id.trim().chars().all(|c| c == '0')
+}
+
+/// rejects inline text longer than `MAX_INLINE_TEXT_LEN` bytes
+pub fn check_inline_text(msg: &ChatMessage) -> ProgramResult {
+    match &msg.inline_text {
+        Some(text) if text.len() > MAX_INLINE_TEXT_LEN => {
+            msg!("Inline text of {} bytes exceeds the {} byte limit.", text.len(), MAX_INLINE_TEXT_LEN);
+            Err(ProgramError::InvalidInstructionData)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// validates a message before it is stored: its inline text (see `check_inline_text`),
+/// its archive_id and its created_on, so every stored slot fits `MAX_CHAT_MESSAGE_LEN`
+pub fn check_message(msg: &ChatMessage) -> ProgramResult {
+    check_inline_text(msg)?;
+    let is_base64url = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if msg.archive_id.len() > DUMMY_TX_ID.len() || !msg.archive_id.chars().all(is_base64url) || is_dummy_slot(msg) {
+        msg!("Invalid archive id {:?}.", msg.archive_id);
+        return Err(ChatError::InvalidArchiveId.into());
+    }
+    if msg.created_on.is_empty() || msg.created_on.len() > DUMMY_CREATED_ON.len() || !msg.created_on.chars().all(|c| c.is_ascii_digit()) {
+        msg!("Invalid timestamp {:?}.", msg.created_on);
+        return Err(ChatError::InvalidTimestamp.into());
+    }
+    Ok(())
+}
+
+/// decodes the messages at the start of the account data. accounts may be sized for
+/// inline text (`CHAT_MESSAGES_ACCOUNT_LEN`), so bytes past the messages are ignored.
+/// a zeroed account decodes as no messages and is reported as `InvalidData`,
+/// the same as an account exactly as long as its messages would be
+fn unpack_chat_messages(data: &[u8]) -> std::io::Result<Vec<ChatMessage>> {
+    unpack_chat_account(data).map(|(messages, _)| messages)
+}
+
+/// decodes the messages like `unpack_chat_messages` and the nonce of the last write stored
+/// right after them. an account without room for the nonce, or with zeroes there, hasn't
+/// accepted a write with a nonce yet and reads as 0
+fn unpack_chat_account(data: &[u8]) -> std::io::Result<(Vec<ChatMessage>, u64)> {
+    let mut rest = data;
+    let messages = read_chat_messages(&mut rest)?;
+    if messages.is_empty() {
+        return Err(std::io::Error::new(InvalidData, "Account data holds no message slots"));
+    }
+    let nonce = u64::deserialize(&mut rest).unwrap_or(0);
+    Ok((messages, nonce))
+}
+
+/// decodes the length prefixed messages at the start of `buf` and advances it past them.
+/// exactly as many messages as the u32 prefix says are read, so the padding of a larger
+/// account is never decoded; a prefix counting more slots than the bytes left could
+/// hold is rejected as `InvalidData` up front
+pub fn read_chat_messages(buf: &mut &[u8]) -> std::io::Result<Vec<ChatMessage>> {
+    let len = u32::deserialize(buf)? as usize;
+    if len > buf.len() / MIN_CHAT_MESSAGE_LEN {
+        return Err(std::io::Error::new(InvalidData, format!("{} message slots don't fit in {} bytes", len, buf.len())));
+    }
+    let mut messages = Vec::with_capacity(len);
+    for _ in 0..len {
+        messages.push(ChatMessage::deserialize(buf)?);
+    }
+    Ok(messages)
+}
+
+/// a write has to carry the nonce following the account's `stored` one, so a replayed or
+/// reordered instruction fails with `ChatError::InvalidNonce`
+pub fn check_nonce(stored: u64, nonce: u64) -> ProgramResult {
+    if stored.checked_add(1) != Some(nonce) {
+        msg!("Expected nonce {}, found {}.", stored.wrapping_add(1), nonce);
+        return Err(ChatError::InvalidNonce.into());
+    }
+    Ok(())
+}
+
+/// number of message slots in the account data, read from the length prefix alone without
+/// decoding the messages. `None` if the data is shorter than the prefix; a zeroed
+/// account has no slots
+pub fn buffer_capacity_from_bytes(data: &[u8]) -> Option<usize> {
+    let prefix = data.get(..4)?;
+    usize::try_from(u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]])).ok()
+}
+
+/// true for a message with a full length archive_id and created_on and no inline text,
+/// the only kind serializing to `FIXED_CHAT_MESSAGE_LEN` bytes
+pub fn is_fixed_slot(msg: &ChatMessage) -> bool {
+    msg.inline_text.is_none() && msg.archive_id.len() == DUMMY_TX_ID.len() && msg.created_on.len() == DUMMY_CREATED_ON.len()
+}
+
+/// the borsh bytes of a single slot, for overwriting it in place at `slot_byte_range`
+/// instead of reserializing every message. fails with `ChatError::SlotLengthMismatch`
+/// unless the message is a fixed length one, see `is_fixed_slot`
+pub fn serialize_slot(msg: &ChatMessage) -> std::result::Result<Vec<u8>, ProgramError> {
+    if !is_fixed_slot(msg) {
+        return Err(ChatError::SlotLengthMismatch.into());
+    }
+    Ok(msg.try_to_vec()?)
+}
+
+/// bytes of the slot at `index` in account data of `capacity` slots, past the length
+/// prefix. assumes every slot is `FIXED_CHAT_MESSAGE_LEN` long; `None` past the last slot
+pub fn slot_byte_range(index: usize, capacity: usize) -> Option<Range<usize>> {
+    if index >= capacity {
+        return None;
+    }
+    let start = 4 + index * FIXED_CHAT_MESSAGE_LEN;
+    Some(start..start + FIXED_CHAT_MESSAGE_LEN)
+}
+
+/// number of slots holding a real (non-dummy) message
+pub fn count_active_messages(messages: &[ChatMessage]) -> usize {
+    messages.iter().filter(|m| !is_dummy_slot(m)).count()
+}
+
+/// indices of every free (dummy) slot, in slot order
+pub fn free_slot_indices(messages: &[ChatMessage]) -> Vec<usize> {
+    messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| is_dummy_slot(m))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// returns the messages created within `from_ms..=to_ms`, skipping dummy entries
+/// and entries whose `created_on` can't be parsed
+pub fn filter_messages_by_time(messages: &[ChatMessage], from_ms: u64, to_ms: u64) -> Vec<&ChatMessage> {
+    messages
+        .iter()
+        .filter(|m| !is_dummy_slot(m))
+        .filter(|m| match m.created_on.parse::<u64>() {
+            Ok(created_on) => from_ms <= created_on && created_on <= to_ms,
+            Err(_) => false,
+        })
+        .collect()
+}
+
+/// returns the messages written by `sender`, skipping dummy entries
+pub fn messages_from<'a>(messages: &'a [ChatMessage], sender: &Pubkey) -> Vec<&'a ChatMessage> {
+    messages
+        .iter()
+        .filter(|m| !is_dummy_slot(m) && m.sender == *sender)
+        .collect()
+}
+
+/// returns the messages in chronological order, skipping dummy entries and entries
+/// whose `created_on` can't be parsed. messages with equal timestamps keep their slot order
+pub fn sorted_by_time(messages: &[ChatMessage]) -> Vec<&ChatMessage> {
+    let mut timed: Vec<(u64, &ChatMessage)> = messages
+        .iter()
+        .filter(|m| !is_dummy_slot(m))
+        .filter_map(|m| m.created_on.parse::<u64>().ok().map(|created_on| (created_on, m)))
+        .collect();
+    timed.sort_by_key(|(created_on, _)| *created_on);
+    timed.into_iter().map(|(_, m)| m).collect()
+}
+
+/// failures of the chat instructions, returned to clients as `ProgramError::Custom(code)`
+/// with the variant's position as the code
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChatError {
+    /// every slot already holds a message
+    BufferFull,
+    /// archive_id isn't an arweave tx id of at most 43 base64url characters, or is
+    /// dummy data for a message without inline text
+    InvalidArchiveId,
+    /// created_on isn't a timestamp of at most 16 digits
+    InvalidTimestamp,
+    /// the chat account isn't owned by the program
+    NotOwner,
+    /// the chat account can't hold the messages and can't be grown to
+    AccountTooSmall,
+    /// the write doesn't carry the nonce after the account's, see `check_nonce`
+    InvalidNonce,
+    /// the slot index is past the last slot of the chat account
+    InvalidSlotIndex,
+    /// the message isn't `FIXED_CHAT_MESSAGE_LEN` bytes long, so it can't overwrite a slot in place
+    SlotLengthMismatch,
+    /// the slot holds a message of another sender, which only that sender may overwrite
+    SlotTaken,
+}
+
+impl From<ChatError> for ProgramError {
+    fn from(err: ChatError) -> Self {
+        ProgramError::Custom(err as u32)
+    }
+}
+
+/// instructions understood by `process_instruction`, borsh encoded behind a variant byte
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub enum ChatInstruction {
+    /// stores a message in the first free slot. `nonce` has to follow the account's, see `check_nonce`
+    AddMessage { nonce: u64, message: ChatMessage },
+    /// stores messages in the free slots in order, stopping once the buffer is full.
+    /// the batch takes a single `nonce`, like `AddMessage`
+    AddMessages { nonce: u64, messages: Vec<ChatMessage> },
+    /// moves every message to the front of the buffer, see `compact`. only the chat
+    /// account itself may compact, so a separate sender is rejected
+    Compact,
+    /// stores a message in the slot at `index`, replacing a free slot or the sender's own
+    /// message, see `write_at`.
+    /// `nonce` has to follow the account's, like for `AddMessage`
+    WriteAt { nonce: u64, index: u16, message: ChatMessage },
+    /// reports how many slots are free as little endian u64 return data, so clients can tell
+    /// when to allocate a new chat account. nothing is written
+    FreeSlots,
+}
+
+/// leading instruction byte selecting the handler, the rest of the data is passed on
+pub const CHAT_INSTRUCTION: u8 = 0;
+pub const GET_PRICE_INSTRUCTION: u8 = 1;
+pub const GET_MEDIAN_PRICE_INSTRUCTION: u8 = 2;
 
+/// handles every instruction that isn't one of `dog_money`'s, which anchor hands to
+/// `dog_money::fallback`. routes on the first instruction byte to
+/// `process_instruction`, `get_price` or `get_median_price`
+pub fn dispatch_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8]
+) -> ProgramResult {
+    let (discriminant, instruction_data) = instruction_data.split_first().ok_or_else(|| {
+        msg!("Missing instruction discriminant");
+        ProgramError::InvalidInstructionData
+    })?;
+    match *discriminant {
+        CHAT_INSTRUCTION => process_instruction(program_id, accounts, instruction_data),
+        GET_PRICE_INSTRUCTION => get_price(program_id, accounts, instruction_data),
+        GET_MEDIAN_PRICE_INSTRUCTION => get_median_price(program_id, accounts, instruction_data),
+        discriminant => {
+            msg!("Unknown instruction discriminant {}", discriminant);
+            Err(ProgramError::InvalidInstructionData)
+        }
+    }
+}
 
 pub fn process_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     instruction_data: &[u8]
 ) -> ProgramResult {
+    if instruction_data.is_empty() {
+        msg!("Missing chat instruction data");
+        return Err(ProgramError::InvalidInstructionData);
+    }
     let accounts_iter = &mut accounts.iter();
     let account = next_account_info(accounts_iter)?;
+    // the sender is an optional second account, otherwise the chat account writes for itself.
+    // a separate sender also pays for growing the chat account, given the system program next
+    let sender = next_account_info(accounts_iter).unwrap_or(account);
+    let system_program = next_account_info(accounts_iter).ok();
     if account.owner != program_id {
         msg!("This account {} is not owned by this program {} and cannot be updated!", account.key, program_id);
+        return Err(ChatError::NotOwner.into());
+    }
+    if !sender.is_signer {
+        msg!("Sender {} has not signed the transaction!", sender.key);
+        return Err(ProgramError::MissingRequiredSignature);
     }
 
     sol_log_compute_units();
 
-    let instruction_data_message = ChatMessage::try_from_slice(instruction_data).map_err(|err| {
+    let instruction = ChatInstruction::try_from_slice(instruction_data).map_err(|err| {
         msg!("Attempt to deserialize instruction data has failed. {:?}", err);
         ProgramError::InvalidInstructionData
     })?;
-    msg!("Instruction_data message object {:?}", instruction_data_message);
+    msg!("Instruction_data object {:?}", instruction);
+
+    match instruction {
+        ChatInstruction::AddMessage { nonce, message } => {
+            write_messages(account, sender, system_program, nonce, vec![message])?;
+        }
+        ChatInstruction::AddMessages { nonce, messages } => {
+            let count = messages.len();
+            let written = write_messages(account, sender, system_program, nonce, messages)?;
+            msg!("Wrote {} of {} messages.", written, count);
+        }
+        ChatInstruction::Compact => {
+            if sender.key != account.key {
+                msg!("Only the chat account {} may compact its messages.", account.key);
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            compact_messages(account)?;
+        }
+        ChatInstruction::WriteAt { nonce, index, message } => {
+            write_message_at(account, sender, system_program, nonce, usize::from(index), message)?;
+        }
+        ChatInstruction::FreeSlots => {
+            let free = free_slot_count(account)?;
+            msg!("{} free slots.", free);
+            set_return_data(&(free as u64).to_le_bytes());
+        }
+    }
+    sol_log_compute_units();
+
+    msg!("End program.");
+    Ok(())
+}
+
+/// writes `message` to the first free slot of `messages` and returns its index, or
+/// fails with `ChatError::BufferFull` when every slot is taken. this is the account-free
+/// core of `process_instruction`, usable for simulating what an instruction would store
+pub fn apply_message(messages: &mut Vec<ChatMessage>, message: ChatMessage) -> std::result::Result<usize, ProgramError> {
+    apply_to_slots(messages, message)
+}
+
+/// writes `message` to the slot at `index` of `messages`, so clients can place or edit a
+/// message deterministically. fails with `ChatError::InvalidSlotIndex` past the last slot,
+/// and with `ChatError::SlotTaken` if the slot holds a message of a sender other than
+/// `message.sender`
+pub fn write_at(messages: &mut [ChatMessage], index: usize, message: ChatMessage) -> ProgramResult {
+    check_message(&message)?;
+    let capacity = messages.len();
+    let slot = messages.get_mut(index).ok_or_else(|| {
+        msg!("Slot index {} is past the last of {} slots.", index, capacity);
+        ChatError::InvalidSlotIndex
+    })?;
+    if !is_dummy_slot(slot) && slot.sender != message.sender {
+        msg!("Slot {} holds a message of {}, not of {}.", index, slot.sender, message.sender);
+        return Err(ChatError::SlotTaken.into());
+    }
+    *slot = message;
+    Ok(())
+}
+
+fn apply_to_slots(slots: &mut [ChatMessage], message: ChatMessage) -> std::result::Result<usize, ProgramError> {
+    check_message(&message)?;
+    // find first dummy data entry
+    let index = slots.iter().position(is_dummy_slot).ok_or(ChatError::BufferFull)?;
+    slots[index] = message; // set dummy data to new entry
+    Ok(index)
+}
+
+/// the chat slots as a buffer of exactly `N` messages. it serializes like the
+/// `Vec<ChatMessage>` account data, a u32 slot count followed by the slots, so it
+/// reads and writes the same accounts; data with any other slot count is rejected.
+/// the type only fixes the slot count, overflowing it is still caught at runtime:
+/// `add` fails with `ChatError::BufferFull` once every slot holds a message
+#[derive(Debug)]
+pub struct MessageBuffer<const N: usize> {
+    messages: [ChatMessage; N],
+}
+
+impl<const N: usize> MessageBuffer<N> {
+    /// a buffer of dummy slots
+    pub fn new() -> Self {
+        MessageBuffer { messages: [(); N].map(|_| get_init_chat_message()) }
+    }
+
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    /// writes `message` to the first free slot and returns its index, see `apply_message`.
+    /// fails with `ChatError::BufferFull` when all `N` slots hold a message
+    pub fn add(&mut self, message: ChatMessage) -> std::result::Result<usize, ProgramError> {
+        apply_to_slots(&mut self.messages, message)
+    }
+
+    /// frees the slot at `index`, failing with `InvalidArgument` past the last slot
+    pub fn reset(&mut self, index: usize) -> ProgramResult {
+        let slot = self.messages.get_mut(index).ok_or(ProgramError::InvalidArgument)?;
+        *slot = get_init_chat_message();
+        Ok(())
+    }
+
+    pub fn free_slot_indices(&self) -> Vec<usize> {
+        free_slot_indices(&self.messages)
+    }
+
+    pub fn count_active_messages(&self) -> usize {
+        count_active_messages(&self.messages)
+    }
+}
+
+impl<const N: usize> Default for MessageBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> BorshSerialize for MessageBuffer<N> {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        (N as u32).serialize(writer)?;
+        for message in self.messages.iter() {
+            message.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> BorshDeserialize for MessageBuffer<N> {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        let len = u32::deserialize(buf)? as usize;
+        if len != N {
+            return Err(std::io::Error::new(InvalidData, format!("Expected {} message slots, found {}", N, len)));
+        }
+        let mut messages = Vec::with_capacity(N);
+        for _ in 0..N {
+            messages.push(ChatMessage::deserialize(buf)?);
+        }
+        let messages = <[ChatMessage; N]>::try_from(messages)
+            .map_err(|_| std::io::Error::new(InvalidData, "Message slot count changed while decoding"))?;
+        Ok(MessageBuffer { messages })
+    }
+}
+
+/// stores `messages`, sent by `sender`, in the free slots of `account` in order and
+/// returns how many were written. writing stops when no free slot is left; if there
+/// is no room for the first message it fails with `ChatError::BufferFull`.
+/// `nonce` is checked against the account's and stored after the messages, see `check_nonce`.
+/// an account too small for the messages is grown, see `grow_chat_account`
+fn write_messages<'a>(
+    account: &AccountInfo<'a>,
+    sender: &AccountInfo<'a>,
+    system_program: Option<&AccountInfo<'a>>,
+    nonce: u64,
+    messages: Vec<ChatMessage>,
+) -> std::result::Result<usize, ProgramError> {
+    if messages.is_empty() {
+        return Ok(0);
+    }
+    for message in messages.iter() {
+        check_message(message)?;
+    }
+
+    let (mut existing_data_messages, stored_nonce, in_place) = load_chat_account_for_write(account)?;
+    check_nonce(stored_nonce, nonce)?;
+    let mut written = Vec::new();
+    for mut message in messages {
+        message.sender = *sender.key;
+        let index = match apply_message(&mut existing_data_messages, message) {
+            Ok(index) => index,
+            Err(_) => break,
+        };
+        msg!("Found index {}", index);
+        written.push(index);
+    }
+    if written.is_empty() {
+        msg!("No free slot left in account data.");
+        return Err(ChatError::BufferFull.into());
+    }
+    store_slots(account, sender, system_program, in_place, &existing_data_messages, &written, nonce)?;
+    let written = written.len();
+    msg!("{} ChatMessages have been saved to account data.", written);
+    Ok(written)
+}
+
+/// stores `message`, sent by `sender`, in the slot at `index` of `account`, replacing a free
+/// slot or a message of `sender`, see `write_at`. `nonce` is checked like in `write_messages`
+fn write_message_at<'a>(
+    account: &AccountInfo<'a>,
+    sender: &AccountInfo<'a>,
+    system_program: Option<&AccountInfo<'a>>,
+    nonce: u64,
+    index: usize,
+    mut message: ChatMessage,
+) -> ProgramResult {
+    check_message(&message)?;
+    let (mut messages, stored_nonce, in_place) = load_chat_account_for_write(account)?;
+    check_nonce(stored_nonce, nonce)?;
+    message.sender = *sender.key;
+    write_at(&mut messages, index, message)?;
+    store_slots(account, sender, system_program, in_place, &messages, &[index], nonce)?;
+    msg!("ChatMessage has been saved to slot {}.", index);
+    Ok(())
+}
 
-    let mut existing_data_messages = match <Vec<ChatMessage>>::try_from_slice(&account.data.borrow_mut()) {
-        Ok(data) => data,
+/// true for account data that is allocated but was never written, every byte still zero
+pub fn is_zeroed(data: &[u8]) -> bool {
+    !data.is_empty() && data.iter().all(|b| *b == 0)
+}
+
+/// the messages and nonce stored in `account`, or dummy slots and nonce 0 for an account
+/// that holds no messages yet, either freshly allocated (see `is_zeroed`) or decoding
+/// as `InvalidData`
+fn load_chat_account(account: &AccountInfo) -> std::result::Result<(Vec<ChatMessage>, u64), ProgramError> {
+    let (messages, nonce, _) = decode_chat_account(&account.data.borrow())?;
+    Ok((messages, nonce))
+}
+
+/// `load_chat_account` of account data, and whether the messages were stored in it
+/// rather than made up for an account holding none yet
+fn decode_chat_account(data: &[u8]) -> std::result::Result<(Vec<ChatMessage>, u64, bool), ProgramError> {
+    if is_zeroed(data) {
+        msg!("Zeroed account data so initializing account data");
+        return Ok((get_init_chat_messages(), 0, false));
+    }
+    match unpack_chat_account(data) {
+        Ok((messages, nonce)) => Ok((messages, nonce, true)),
         Err(err) => {
             if err.kind() == InvalidData {
                 msg!("InvalidData so initializing account data");
-                get_init_chat_messages()
+                Ok((get_init_chat_messages(), 0, false))
             } else {
-                panic!("Unknown error decoding account data {:?}", err)
+                msg!("Unknown error decoding account data {:?}", err);
+                Err(ProgramError::InvalidAccountData)
             }
         }
-    };
-    let index = existing_data_messages.iter().position(|p| p.archive_id == String::from(DUMMY_TX_ID)).unwrap(); // find first dummy data entry
-    msg!("Found index {}", index);
-    existing_data_messages[index] = instruction_data_message; // set dummy data to new entry
-    let updated_data = existing_data_messages.try_to_vec().expect("Failed to encode data."); // set messages object back to vector data
-    msg!("Final existing_data_messages[index] {:?}", existing_data_messages[index]);
+    }
+}
+
+/// the messages and nonce like `load_chat_account`, and whether the account data already holds
+/// them as fixed length slots (see `is_fixed_slot`) with room for the nonce, so `store_slots`
+/// can overwrite single slots in place. an account holding no messages yet never can
+fn load_chat_account_for_write(account: &AccountInfo) -> std::result::Result<(Vec<ChatMessage>, u64, bool), ProgramError> {
+    let (messages, nonce, stored) = decode_chat_account(&account.data.borrow())?;
+    let in_place = stored
+        && messages.iter().all(is_fixed_slot)
+        && account.data_len() >= 4 + messages.len() * FIXED_CHAT_MESSAGE_LEN + 8;
+    Ok((messages, nonce, in_place))
+}
+
+/// writes the slots at `indices` of `messages` and `nonce` to `account`. with `in_place`
+/// (see `load_chat_account_for_write`) and fixed length new slots only their bytes at
+/// `slot_byte_range` and the nonce are overwritten, otherwise everything is saved, see `save_chat_account`
+fn store_slots<'a>(
+    account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: Option<&AccountInfo<'a>>,
+    in_place: bool,
+    messages: &[ChatMessage],
+    indices: &[usize],
+    nonce: u64,
+) -> ProgramResult {
+    if !in_place || !indices.iter().all(|index| messages.get(*index).is_some_and(is_fixed_slot)) {
+        return save_chat_account(account, payer, system_program, messages, nonce);
+    }
+    let capacity = messages.len();
+    let mut data = account.data.borrow_mut();
+    for index in indices {
+        let range = slot_byte_range(*index, capacity).ok_or(ChatError::InvalidSlotIndex)?;
+        data[range].copy_from_slice(&serialize_slot(&messages[*index])?);
+    }
+    let nonce_start = 4 + capacity * FIXED_CHAT_MESSAGE_LEN;
+    data[nonce_start..nonce_start + 8].copy_from_slice(&nonce.to_le_bytes());
+    msg!("Overwrote {} slots in place.", indices.len());
+    Ok(())
+}
+
+/// writes `messages` followed by `nonce` to `account`, growing it with `payer`'s
+/// lamports when they no longer fit, see `grow_chat_account`
+fn save_chat_account<'a>(
+    account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: Option<&AccountInfo<'a>>,
+    messages: &[ChatMessage],
+    nonce: u64,
+) -> ProgramResult {
+    let mut updated_data = messages.try_to_vec()?; // set messages object back to vector data
+    updated_data.extend_from_slice(&nonce.to_le_bytes());
 
     // data algorithm for storing data into account and then archiving into Arweave
     // 1. Each ChatMessage object will be prepopulated for txt field having 43 characters (length of a arweave tx).
     // Each ChatMessageContainer will be prepopulated with 10 ChatMessage objects with dummy data.
     // 2. Client will submit an arweave tx for each message; get back the tx id; and submit it to our program.
     // 3. This tx id will be saved to the Solana program and be used for querying back to arweave to get actual data.
+    if updated_data.len() > account.data_len() {
+        grow_chat_account(account, payer, system_program, updated_data.len())?;
+    }
     let data = &mut &mut account.data.borrow_mut();
     msg!("Attempting save data.");
-    data[..updated_data.len()].copy_from_slice(&updated_data);    
-    let saved_data = <Vec<ChatMessage>>::try_from_slice(data)?;
-    msg!("ChatMessage has been saved to account data. {:?}", saved_data[index]);
-    sol_log_compute_units();
+    if updated_data.len() > data.len() {
+        msg!("Account data of {} bytes is too small for {} bytes.", data.len(), updated_data.len());
+        return Err(ChatError::AccountTooSmall.into());
+    }
+    data[..updated_data.len()].copy_from_slice(&updated_data);
+    let saved_data = unpack_chat_messages(data)?;
+    msg!("Saved account data. {:?}", saved_data);
+    Ok(())
+}
 
-    msg!("End program.");
+/// moves the real messages to the front of `messages`, keeping their order, and fills
+/// the slots after them with dummy data
+pub fn compact(messages: &mut Vec<ChatMessage>) {
+    let len = messages.len();
+    messages.retain(|m| !is_dummy_slot(m));
+    let free = len - messages.len();
+    write_init_messages_into(messages, free);
+}
+
+/// compacts the messages stored in `account`
+fn compact_messages(account: &AccountInfo) -> ProgramResult {
+    let mut messages = unpack_chat_messages(&account.data.borrow()).map_err(|err| {
+        msg!("Unknown error decoding account data {:?}", err);
+        ProgramError::InvalidAccountData
+    })?;
+    compact(&mut messages);
+    // the same messages take the same bytes, so the nonce after them stays in place
+    let updated_data = messages.try_to_vec()?;
+    let data = &mut account.data.borrow_mut();
+    if updated_data.len() > data.len() {
+        msg!("Account data of {} bytes is too small for {} bytes.", data.len(), updated_data.len());
+        return Err(ChatError::AccountTooSmall.into());
+    }
+    data[..updated_data.len()].copy_from_slice(&updated_data);
+    msg!("Compacted {} messages.", count_active_messages(&messages));
     Ok(())
 }
 
+/// number of free slots in `account`, see `free_slot_indices`. an account holding no
+/// messages yet has every slot free once initialized, see `load_chat_account`
+pub fn free_slot_count(account: &AccountInfo) -> std::result::Result<usize, ProgramError> {
+    let (messages, _) = load_chat_account(account)?;
+    Ok(free_slot_indices(&messages).len())
+}
+
+/// lamports `payer` has to add so an account of `len` bytes holding `lamports` is rent exempt
+pub fn rent_top_up(rent: &Rent, lamports: u64, len: usize) -> u64 {
+    rent.minimum_balance(len).saturating_sub(lamports)
+}
+
+/// reallocates `account` to `new_len` bytes, with `payer` topping up its rent from the system program.
+/// fails with `ChatError::AccountTooSmall` when there is no separate payer or system program, or when
+/// the account would grow by more than an instruction allows (`MAX_PERMITTED_DATA_INCREASE`)
+fn grow_chat_account<'a>(
+    account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: Option<&AccountInfo<'a>>,
+    new_len: usize,
+) -> ProgramResult {
+    let growth = new_len.saturating_sub(account.data_len());
+    if growth > MAX_PERMITTED_DATA_INCREASE {
+        msg!("Account data can grow by {} bytes at most, {} needed.", MAX_PERMITTED_DATA_INCREASE, growth);
+        return Err(ChatError::AccountTooSmall.into());
+    }
+    let system_program = match system_program {
+        Some(system_program) if payer.key != account.key => system_program,
+        _ => {
+            msg!("Account data of {} bytes is too small for {} bytes and no payer was given.", account.data_len(), new_len);
+            return Err(ChatError::AccountTooSmall.into());
+        }
+    };
+    if !system_program::check_id(system_program.key) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let top_up = rent_top_up(&Rent::get()?, account.lamports(), new_len);
+    if top_up > 0 {
+        msg!("Funding {} lamports of rent from {}.", top_up, payer.key);
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, top_up),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    msg!("Growing account data from {} to {} bytes.", account.data_len(), new_len);
+    account.realloc(new_len, false)
+}
+
 
 
 
 #[program]
 pub mod dog_money {
     use super::*;
-    pub fn initialize_user(ctx: Context<InitializeUser>, amount: u64, nonce: u8) -> ProgramResult {
+    /// everything that isn't a `dog_money` instruction, see `dispatch_instruction`
+    pub fn fallback(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> Result<()> {
+        dispatch_instruction(program_id, accounts, data).map_err(Into::into)
+    }
+
+    pub fn initialize_config(ctx: Context<InitializeConfig>, max_deposit: u64) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.authority = *ctx.accounts.authority.key;
+        config.max_deposit = max_deposit;
+        config.paused = false;
+        config.max_price_deviation_bps = None;
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<UpdateConfig>, paused: bool) -> Result<()> {
+        check_authority(&ctx.accounts.config.authority, ctx.accounts.authority.key)?;
+        ctx.accounts.config.paused = paused;
+        Ok(())
+    }
+
+    pub fn set_max_deposit(ctx: Context<UpdateConfig>, max_deposit: u64) -> Result<()> {
+        check_authority(&ctx.accounts.config.authority, ctx.accounts.authority.key)?;
+        ctx.accounts.config.max_deposit = max_deposit;
+        Ok(())
+    }
+
+    pub fn set_max_price_deviation(ctx: Context<UpdateConfig>, max_price_deviation_bps: Option<u64>) -> Result<()> {
+        check_authority(&ctx.accounts.config.authority, ctx.accounts.authority.key)?;
+        ctx.accounts.config.max_price_deviation_bps = max_price_deviation_bps;
+        Ok(())
+    }
+
+    pub fn transfer_authority(ctx: Context<UpdateConfig>, new_authority: Pubkey) -> Result<()> {
+        check_authority(&ctx.accounts.config.authority, ctx.accounts.authority.key)?;
+        ctx.accounts.config.authority = new_authority;
+        Ok(())
+    }
+
+    pub fn initialize_user(ctx: Context<InitializeUser>, amount: u64, _nonce: u8) -> Result<()> {
+        check_token_program(&ctx.accounts.token_program)?;
+        let (total_deposited, dog_money_amount) = deposit_outcome(&ctx.accounts.config, 0, amount)?;
         let user_data = &mut ctx.accounts.user_data;
         user_data.first_deposit = ctx.accounts.clock.unix_timestamp;
+        user_data.total_deposited = total_deposited;
 
         // Transfer USDC from user to vault
         let cpi_accounts = Transfer {
@@ -110,8 +793,7 @@ pub mod dog_money {
         token::transfer(cpi_ctx, amount)?;
 
         // Mint 1,0000x dog money to user account
-        let dog_money_amount = amount.checked_mul(1000).unwrap();
-        let seeds = &[ctx.accounts.usdc_mint.to_account_info().key.as_ref(), &[nonce], ];
+        let seeds = &[ctx.accounts.usdc_mint.to_account_info().key.as_ref(), &[ctx.bumps["program_signer"]], ];
         let signer = &[&seeds[..]];
         let cpi_accounts = MintTo {
             mint: ctx.accounts.dog_money_mint.to_account_info(),
@@ -124,195 +806,2640 @@ pub mod dog_money {
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct InitializeUser<'info> {
-    program_signer: AccountInfo<'info>,
-    #[account(associated = authority, with = usdc_mint)]
-    user_data: ProgramAccount<'info, UserData>,
-    #[account(signer)]
-    authority: AccountInfo<'info>,
-    usdc_mint: CpiAccount<'info, Mint>,
-    #[account(mut, "user_usdc.owner == *authority.key")]
-    user_usdc: CpiAccount<'info, TokenAccount>,
-    #[account(mut)]
-    program_vault: CpiAccount<'info, TokenAccount>,
-    #[account(mut,
-    "dog_money_mint.mint_authority == COption::Some(*program_signer.key)")]
-    dog_money_mint: CpiAccount<'info, Mint>,
-    #[account(mut, "user_dog_money.owner == *authority.key")]
-    user_dog_money: CpiAccount<'info, TokenAccount>,
-    // We already know its address and that it's executable
-    #[account(executable, "token_program.key == &token::ID")]
-    token_program: AccountInfo<'info>,
-    rent: Sysvar<'info, Rent>,
-    system_program: AccountInfo<'info>,
-    clock: Sysvar<'info, Clock>,
-}
+    pub fn deposit(ctx: Context<Deposit>, amount: u64, _nonce: u8) -> Result<()> {
+        check_token_program(&ctx.accounts.token_program)?;
+        let user_data = &mut ctx.accounts.user_data;
+        let (total_deposited, dog_money_amount) =
+            deposit_outcome(&ctx.accounts.config, user_data.total_deposited, amount)?;
+        user_data.total_deposited = total_deposited;
 
+        // Transfer USDC from user to vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_usdc.to_account_info(),
+            to: ctx.accounts.program_vault.to_account_info(),
+            authority: ctx.accounts.authority.clone(),
+        };
+        let cpi_program = ctx.accounts.token_program.clone();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
 
-#[associated]
-pub struct UserData {
-    pub first_deposit: i64,
-}
+        // Mint 1,0000x dog money to user account
+        let seeds = &[ctx.accounts.usdc_mint.to_account_info().key.as_ref(), &[ctx.bumps["program_signer"]], ];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.dog_money_mint.to_account_info(),
+            to: ctx.accounts.user_dog_money.to_account_info(),
+            authority: ctx.accounts.program_signer.clone()
+        };
+        let cpi_program = ctx.accounts.token_program.clone();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::mint_to(cpi_ctx, dog_money_amount)?;
 
-struct Decimal {
-    pub value: u128,
-    pub decimals: u32,
-}
+        Ok(())
+    }
 
-/// Define the type of state stored in accounts
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub struct PriceFeedAccount {
-    /// number of greetings
-    pub answer: u128,
-}
+    /// Returns, as little endian u64 return data, the dog money `deposit` would mint for
+    /// `amount` on top of the user's deposits so far under the current config. Nothing is
+    /// transferred or minted.
+    pub fn simulate_deposit(ctx: Context<SimulateDeposit>, amount: u64) -> Result<()> {
+        let deposited = ctx.accounts.user_data.total_deposited;
+        let (_, dog_money_amount) = deposit_outcome(&ctx.accounts.config, deposited, amount)?;
+        set_return_data(&dog_money_amount.to_le_bytes());
+        Ok(())
+    }
 
-impl Decimal {
-    pub fn new(value: u128, decimals: u32) -> Self {
-        Decimal { value, decimals }
+    /// Returns, as little endian u64 return data, the yield the user's deposits accrued at
+    /// `rate_per_sec` since their first deposit, see `accrued_amount`
+    pub fn accrued_yield(ctx: Context<AccruedYield>, rate_per_sec: u64) -> Result<()> {
+        let user_data = &ctx.accounts.user_data;
+        let elapsed_secs = ctx.accounts.clock.unix_timestamp.saturating_sub(user_data.first_deposit);
+        let accrued = accrued_amount(user_data.total_deposited, rate_per_sec, elapsed_secs)
+            .ok_or(ErrorCode::MathOverflow)?;
+        set_return_data(&accrued.to_le_bytes());
+        Ok(())
     }
-}
 
-impl std::fmt::Display for Decimal {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut scaled_val = self.value.to_string();
-        if scaled_val.len() <= self.decimals as usize {
-            scaled_val.insert_str(
-                0,
-                &vec!["0"; self.decimals as usize - scaled_val.len()].join(""),
-            );
-            scaled_val.insert_str(0, "0.");
-        } else {
-            scaled_val.insert(scaled_val.len() - self.decimals as usize, '.');
-        }
-        f.write_str(&scaled_val)
+    /// Creates the USDC vault of `usdc_mint`, held by its program signer
+    pub fn initialize_vault(_ctx: Context<InitializeVault>) -> Result<()> {
+        Ok(())
     }
-}
 
-// Declare and export the program's entrypoint
-entrypoint!(get_price);
+    /// Burns `dog_money_amount` and pays out the USDC it is worth from the vault, net
+    /// of the pool's withdraw fee, which stays in the vault.
+    /// like for deposits, `nonce` is kept for the instruction layout and the program signer
+    /// signs with its derived bump
+    pub fn redeem(ctx: Context<Redeem>, dog_money_amount: u64, _nonce: u8) -> Result<()> {
+        check_token_program(&ctx.accounts.token_program)?;
+        check_backing(ctx.accounts.dog_money_mint.supply, ctx.accounts.program_vault.amount)?;
+        let fees = Fees::from(ctx.accounts.pool_fees.fees);
+        let (burned, net_usdc, fee) = redeem_outcome(dog_money_amount, &fees)?;
+        msg!("Redeeming {} dog money for {} USDC, {} USDC withdraw fee", burned, net_usdc, fee);
+        let user_data = &mut ctx.accounts.user_data;
+        user_data.total_deposited = user_data.total_deposited.saturating_sub(net_usdc.saturating_add(fee));
 
-// Program entrypoint's implementation
-pub fn get_price(
-    _program_id: &Pubkey, // Ignored
-    accounts: &[AccountInfo], // Public key of the account to read price data from
-    _instruction_data: &[u8], // Ignored
-) -> ProgramResult {
-    msg!("Chainlink Solana Demo program entrypoint");
+        // Burn the redeemed dog money
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.dog_money_mint.to_account_info(),
+            from: ctx.accounts.user_dog_money.to_account_info(),
+            authority: ctx.accounts.authority.clone(),
+        };
+        let cpi_program = ctx.accounts.token_program.clone();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::burn(cpi_ctx, burned)?;
 
-    let accounts_iter = &mut accounts.iter();
-    // This is the account of our our account
-    let my_account = next_account_info(accounts_iter)?;
+        // Transfer USDC net of the fee from vault to user
+        let seeds = &[ctx.accounts.usdc_mint.to_account_info().key.as_ref(), &[ctx.bumps["program_signer"]], ];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.program_vault.to_account_info(),
+            to: ctx.accounts.user_usdc.to_account_info(),
+            authority: ctx.accounts.program_signer.clone(),
+        };
+        let cpi_program = ctx.accounts.token_program.clone();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, net_usdc)?;
+
+        Ok(())
+    }
+
+    /// Only the config authority may set up the fees, and becomes their admin
+    pub fn initialize_pool_fees(ctx: Context<InitializePoolFees>, fees: PoolFees) -> Result<()> {
+        check_authority(&ctx.accounts.config.authority, ctx.accounts.admin.key)?;
+        let pool_fees = &mut ctx.accounts.pool_fees;
+        pool_fees.admin = *ctx.accounts.admin.key;
+        pool_fees.fees = fees;
+        Ok(())
+    }
+
+    pub fn set_pool_fees(ctx: Context<SetPoolFees>, fees: PoolFees) -> Result<()> {
+        check_authority(&ctx.accounts.pool_fees.admin, ctx.accounts.admin.key)?;
+        ctx.accounts.pool_fees.fees = fees;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(init, payer = authority, space = 8 + Config::LEN, seeds = [CONFIG_SEED], bump)]
+    config: Account<'info, Config>,
+    #[account(mut, signer)]
+    authority: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(mut, seeds = [CONFIG_SEED], bump)]
+    config: Account<'info, Config>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePoolFees<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    config: Account<'info, Config>,
+    #[account(init, payer = admin, space = 8 + PoolFeesAccount::LEN, seeds = [POOL_FEES_SEED], bump)]
+    pool_fees: Account<'info, PoolFeesAccount>,
+    #[account(mut, signer)]
+    admin: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolFees<'info> {
+    #[account(mut, seeds = [POOL_FEES_SEED], bump)]
+    pool_fees: Account<'info, PoolFeesAccount>,
+    #[account(signer)]
+    admin: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeUser<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    config: Account<'info, Config>,
+    #[account(seeds = [usdc_mint.key().as_ref()], bump)]
+    program_signer: AccountInfo<'info>,
+    #[account(init, payer = authority, space = 8 + UserData::LEN,
+    seeds = [USER_DATA_SEED, authority.key.as_ref(), usdc_mint.key().as_ref()], bump)]
+    user_data: Account<'info, UserData>,
+    #[account(mut, signer)]
+    authority: AccountInfo<'info>,
+    usdc_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_usdc.owner == *authority.key,
+    constraint = user_usdc.mint == usdc_mint.key())]
+    user_usdc: Account<'info, TokenAccount>,
+    #[account(mut, seeds = [VAULT_SEED, usdc_mint.key().as_ref()], bump,
+    token::mint = usdc_mint, token::authority = program_signer)]
+    program_vault: Account<'info, TokenAccount>,
+    #[account(mut,
+    constraint = dog_money_mint.mint_authority == COption::Some(*program_signer.key))]
+    dog_money_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_dog_money.owner == *authority.key,
+    constraint = user_dog_money.mint == dog_money_mint.key())]
+    user_dog_money: Account<'info, TokenAccount>,
+    // Checked by `check_token_program` for a clear error on the wrong program
+    token_program: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+    clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct SimulateDeposit<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    config: Account<'info, Config>,
+    #[account(
+    seeds = [USER_DATA_SEED, authority.key.as_ref(), usdc_mint.key().as_ref()], bump)]
+    user_data: Account<'info, UserData>,
+    authority: AccountInfo<'info>,
+    usdc_mint: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    config: Account<'info, Config>,
+    #[account(seeds = [usdc_mint.key().as_ref()], bump)]
+    program_signer: AccountInfo<'info>,
+    #[account(mut,
+    seeds = [USER_DATA_SEED, authority.key.as_ref(), usdc_mint.key().as_ref()], bump)]
+    user_data: Account<'info, UserData>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    usdc_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_usdc.owner == *authority.key,
+    constraint = user_usdc.mint == usdc_mint.key())]
+    user_usdc: Account<'info, TokenAccount>,
+    #[account(mut, seeds = [VAULT_SEED, usdc_mint.key().as_ref()], bump,
+    token::mint = usdc_mint, token::authority = program_signer)]
+    program_vault: Account<'info, TokenAccount>,
+    #[account(mut,
+    constraint = dog_money_mint.mint_authority == COption::Some(*program_signer.key))]
+    dog_money_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_dog_money.owner == *authority.key,
+    constraint = user_dog_money.mint == dog_money_mint.key())]
+    user_dog_money: Account<'info, TokenAccount>,
+    // Checked by `check_token_program`
+    token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AccruedYield<'info> {
+    #[account(
+    seeds = [USER_DATA_SEED, authority.key.as_ref(), usdc_mint.key().as_ref()], bump)]
+    user_data: Account<'info, UserData>,
+    authority: AccountInfo<'info>,
+    usdc_mint: Account<'info, Mint>,
+    clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeVault<'info> {
+    #[account(seeds = [usdc_mint.key().as_ref()], bump)]
+    program_signer: AccountInfo<'info>,
+    usdc_mint: Account<'info, Mint>,
+    #[account(init, payer = payer, seeds = [VAULT_SEED, usdc_mint.key().as_ref()], bump,
+    token::mint = usdc_mint, token::authority = program_signer)]
+    program_vault: Account<'info, TokenAccount>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    token_program: Program<'info, Token>,
+    system_program: Program<'info, System>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct Redeem<'info> {
+    #[account(seeds = [POOL_FEES_SEED], bump)]
+    pool_fees: Account<'info, PoolFeesAccount>,
+    #[account(seeds = [usdc_mint.key().as_ref()], bump)]
+    program_signer: AccountInfo<'info>,
+    #[account(mut,
+    seeds = [USER_DATA_SEED, authority.key.as_ref(), usdc_mint.key().as_ref()], bump)]
+    user_data: Account<'info, UserData>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    usdc_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_usdc.owner == *authority.key,
+    constraint = user_usdc.mint == usdc_mint.key())]
+    user_usdc: Account<'info, TokenAccount>,
+    #[account(mut, seeds = [VAULT_SEED, usdc_mint.key().as_ref()], bump,
+    token::mint = usdc_mint, token::authority = program_signer)]
+    program_vault: Account<'info, TokenAccount>,
+    #[account(mut,
+    constraint = dog_money_mint.mint_authority == COption::Some(*program_signer.key))]
+    dog_money_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_dog_money.owner == *authority.key,
+    constraint = user_dog_money.mint == dog_money_mint.key())]
+    user_dog_money: Account<'info, TokenAccount>,
+    // Checked by `check_token_program`
+    token_program: AccountInfo<'info>,
+}
+
+/// seed of the `UserData` PDA, followed by the user's key and the USDC mint
+pub const USER_DATA_SEED: &[u8] = b"anchor";
+
+/// seed of the USDC vault PDA, followed by the USDC mint. the vault is held by the program
+/// signer, the PDA of the USDC mint alone, which is also the dog money mint authority
+pub const VAULT_SEED: &[u8] = b"vault";
+
+#[account]
+#[derive(Default)]
+pub struct UserData {
+    pub first_deposit: i64,
+    /// USDC deposited so far, bounded by `Config::max_deposit`
+    pub total_deposited: u64,
+}
+
+impl UserData {
+    pub const LEN: usize = 8 + 8;
+}
+
+/// seed of the program's single `Config` PDA, so no other account can stand in for it
+pub const CONFIG_SEED: &[u8] = b"config";
+
+#[account]
+pub struct Config {
+    /// only this key may change the config
+    pub authority: Pubkey,
+    /// max USDC a single user may deposit in total
+    pub max_deposit: u64,
+    /// when set, no new deposits are accepted
+    pub paused: bool,
+    /// how far in basis points the price instructions may move the stored price in one
+    /// update, unbounded if `None`. see `check_price_deviation`
+    pub max_price_deviation_bps: Option<u64>,
+}
+
+impl Config {
+    pub const LEN: usize = 32 + 8 + 1 + 1 + 8;
+}
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Deposit exceeds the configured maximum")]
+    DepositCapExceeded,
+    #[msg("Deposits are paused")]
+    Paused,
+    #[msg("Signer is not the authority of the account")]
+    Unauthorized,
+    #[msg("Dog money amount overflowed")]
+    MathOverflow,
+    #[msg("Token program is not the executable SPL token program")]
+    InvalidTokenProgram,
+    #[msg("Dog money supply exceeds the USDC in the vault")]
+    BackingMismatch,
+}
+
+/// Only the stored authority, `Config::authority` or `PoolFeesAccount::admin`, may change
+/// the account holding it
+pub fn check_authority(authority: &Pubkey, signer: &Pubkey) -> Result<()> {
+    require!(signer == authority, ErrorCode::Unauthorized);
+    Ok(())
+}
+
+/// The token program passed in has to be the executable SPL token program. Checked in the
+/// handlers rather than as an account constraint so a wrong program gets its own error
+pub fn check_token_program(token_program: &AccountInfo) -> Result<()> {
+    if token_program.key != &token::ID || !token_program.executable {
+        msg!("Expected the token program {}, found {}", token::ID, token_program.key);
+        return Err(ErrorCode::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+/// Rejects deposits while the program is paused
+pub fn check_not_paused(paused: bool) -> std::result::Result<(), ErrorCode> {
+    if paused {
+        return Err(ErrorCode::Paused);
+    }
+    Ok(())
+}
+
+/// Returns the user's new deposit total, or an error if it would exceed `max_deposit`
+pub fn check_deposit_cap(max_deposit: u64, deposited: u64, amount: u64) -> std::result::Result<u64, ErrorCode> {
+    match deposited.checked_add(amount) {
+        Some(total) if total <= max_deposit => Ok(total),
+        _ => Err(ErrorCode::DepositCapExceeded),
+    }
+}
+
+/// Dog money minted per USDC deposited
+pub const DOG_MONEY_PER_USDC: u64 = 1000;
+
+/// Checks a deposit of `amount` on top of `deposited` against the config and returns
+/// the user's new deposit total and the dog money minted for it
+pub fn deposit_outcome(config: &Config, deposited: u64, amount: u64) -> std::result::Result<(u64, u64), ErrorCode> {
+    check_not_paused(config.paused)?;
+    let total_deposited = check_deposit_cap(config.max_deposit, deposited, amount)?;
+    let dog_money_amount = amount.checked_mul(DOG_MONEY_PER_USDC).ok_or(ErrorCode::MathOverflow)?;
+    Ok((total_deposited, dog_money_amount))
+}
+
+/// `rate_per_sec` is the yield per second on one unit of principal, over this
+pub const YIELD_RATE_DENOMINATOR: u128 = 1_000_000_000_000;
+
+/// Yield on `principal` after `elapsed_secs` at `rate_per_sec / YIELD_RATE_DENOMINATOR`
+/// per second, rounded down. `None` if time went backwards or the yield overflows a u64
+pub fn accrued_amount(principal: u64, rate_per_sec: u64, elapsed_secs: i64) -> Option<u64> {
+    let elapsed_secs = u64::try_from(elapsed_secs).ok()?;
+    let accrued = (principal as u128)
+        .checked_mul(rate_per_sec.into())?
+        .checked_mul(elapsed_secs.into())?
+        / YIELD_RATE_DENOMINATOR;
+    u64::try_from(accrued).ok()
+}
+
+/// Splits redeeming `dog_money_amount` into the dog money burned, the USDC paid out and
+/// the withdraw fee kept, together the gross USDC at `DOG_MONEY_PER_USDC`. Only dog money
+/// worth whole USDC units is burned, the remainder stays with the user
+pub fn redeem_outcome(dog_money_amount: u64, fees: &Fees) -> std::result::Result<(u64, u64, u64), ErrorCode> {
+    let gross_usdc = dog_money_amount / DOG_MONEY_PER_USDC;
+    let burned = gross_usdc * DOG_MONEY_PER_USDC;
+    let fee = fees.withdraw_fee(gross_usdc).ok_or(ErrorCode::MathOverflow)?;
+    let net_usdc = gross_usdc.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+    Ok((burned, net_usdc, fee))
+}
+
+/// Every dog money in `dog_money_supply` was minted for USDC at `DOG_MONEY_PER_USDC`, and
+/// redeems burn it at the same rate while paying out at most that, so the vault always
+/// holds at least `dog_money_supply / DOG_MONEY_PER_USDC`. Withdraw fees left in the vault
+/// only add to it. Anything less means a mint or burn went wrong, and redeeming is refused.
+/// `usdc_in_vault` has to be the balance of the vault PDA, see `VAULT_SEED`
+pub fn check_backing(dog_money_supply: u64, usdc_in_vault: u64) -> std::result::Result<(), ErrorCode> {
+    let backed = usdc_in_vault as u128 * DOG_MONEY_PER_USDC as u128;
+    if dog_money_supply as u128 > backed {
+        msg!("{} dog money is backed by only {} USDC", dog_money_supply, usdc_in_vault);
+        return Err(ErrorCode::BackingMismatch);
+    }
+    Ok(())
+}
+
+/// USDC one dog money redeems for, `vault_usdc / dog_money_supply` with `decimals` places,
+/// rounded down. Before any fees have accrued this is `1 / DOG_MONEY_PER_USDC`.
+/// `None` while no dog money exists
+pub fn dog_money_price_in_usdc(vault_usdc: u64, dog_money_supply: u64, decimals: u32) -> Option<Decimal> {
+    let value = (vault_usdc as u128)
+        .checked_mul(10u128.checked_pow(decimals)?)?
+        .checked_div(dog_money_supply.into())?;
+    Some(Decimal::new(value, decimals))
+}
+
+/// Borsh-serializable mirror of the swap client's `Fees`
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolFees {
+    pub admin_trade_fee_numerator: u64,
+    pub admin_trade_fee_denominator: u64,
+    pub admin_withdraw_fee_numerator: u64,
+    pub admin_withdraw_fee_denominator: u64,
+    pub trade_fee_numerator: u64,
+    pub trade_fee_denominator: u64,
+    pub withdraw_fee_numerator: u64,
+    pub withdraw_fee_denominator: u64,
+}
+
+impl From<Fees> for PoolFees {
+    fn from(fees: Fees) -> Self {
+        PoolFees {
+            admin_trade_fee_numerator: fees.admin_trade_fee_numerator,
+            admin_trade_fee_denominator: fees.admin_trade_fee_denominator,
+            admin_withdraw_fee_numerator: fees.admin_withdraw_fee_numerator,
+            admin_withdraw_fee_denominator: fees.admin_withdraw_fee_denominator,
+            trade_fee_numerator: fees.trade_fee_numerator,
+            trade_fee_denominator: fees.trade_fee_denominator,
+            withdraw_fee_numerator: fees.withdraw_fee_numerator,
+            withdraw_fee_denominator: fees.withdraw_fee_denominator,
+        }
+    }
+}
+
+impl From<PoolFees> for Fees {
+    fn from(fees: PoolFees) -> Self {
+        Fees {
+            admin_trade_fee_numerator: fees.admin_trade_fee_numerator,
+            admin_trade_fee_denominator: fees.admin_trade_fee_denominator,
+            admin_withdraw_fee_numerator: fees.admin_withdraw_fee_numerator,
+            admin_withdraw_fee_denominator: fees.admin_withdraw_fee_denominator,
+            trade_fee_numerator: fees.trade_fee_numerator,
+            trade_fee_denominator: fees.trade_fee_denominator,
+            withdraw_fee_numerator: fees.withdraw_fee_numerator,
+            withdraw_fee_denominator: fees.withdraw_fee_denominator,
+        }
+    }
+}
+
+/// seed of the program's single `PoolFeesAccount` PDA, the fees `redeem` charges
+pub const POOL_FEES_SEED: &[u8] = b"pool_fees";
+
+/// Fee configuration of a pool, initialized once by the config authority
+#[account]
+#[derive(Default)]
+pub struct PoolFeesAccount {
+    /// only this key may update the fees
+    pub admin: Pubkey,
+    pub fees: PoolFees,
+}
+
+impl PoolFeesAccount {
+    pub const LEN: usize = 32 + 8 * 8;
+}
+
+/// `(reserve_in, reserve_out)` of a pool, read from the data of its source and destination
+/// token accounts, for quoting swaps off chain with the `swap_math` curves
+pub fn reserves_from_token_accounts(source: &[u8], destination: &[u8]) -> std::result::Result<(u64, u64), ProgramError> {
+    let amount = |mut data: &[u8]| TokenAccount::try_deserialize(&mut data).map(|account| account.amount);
+    Ok((amount(source)?, amount(destination)?))
+}
+
+/// layout version written by `get_price`
+pub const PRICE_FEED_ACCOUNT_VERSION: u8 = 3;
+/// decimals of the answers in v1 accounts, which predate storing the feed's decimals
+const PRICE_DECIMALS: u8 = 9;
+
+/// Define the type of state stored in accounts
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, PartialEq)]
+pub struct PriceFeedAccount {
+    /// layout version, see `deserialize_versioned`
+    pub version: u8,
+    /// last price read from the feed
+    pub answer: u128,
+    /// unix timestamp of the last update
+    pub timestamp: i64,
+    /// decimals of `answer`
+    pub decimals: u8,
+    /// sum of `answer * seconds` it was stored for, wrapping on overflow;
+    /// see `twap` for the average between two snapshots
+    pub cumulative_price: u128,
+}
+
+impl PriceFeedAccount {
+    /// decodes any layout written so far and upgrades it to the current version:
+    /// - 0: a new, zeroed account
+    /// - 1: `version, answer`, with the feed's default decimals and no timestamp
+    /// - 2: `version, answer, timestamp, decimals`
+    /// - 3: `version, answer, timestamp, decimals, cumulative_price`
+    ///   bytes past the layout are ignored so accounts can be sized for later versions
+    pub fn deserialize_versioned(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        let mut account = match data.first() {
+            Some(0) => PriceFeedAccount::default(),
+            Some(1) => PriceFeedAccount {
+                answer: u128::deserialize(&mut &data[1..])?,
+                decimals: PRICE_DECIMALS,
+                ..PriceFeedAccount::default()
+            },
+            Some(2) => {
+                let fields = &mut &data[1..];
+                PriceFeedAccount {
+                    answer: u128::deserialize(fields)?,
+                    timestamp: i64::deserialize(fields)?,
+                    decimals: u8::deserialize(fields)?,
+                    ..PriceFeedAccount::default()
+                }
+            }
+            Some(3) => PriceFeedAccount::deserialize(&mut &data[..])?,
+            version => {
+                msg!("Unknown price feed account version {:?}", version);
+                return Err(ProgramError::InvalidAccountData);
+            }
+        };
+        account.version = PRICE_FEED_ACCOUNT_VERSION;
+        Ok(account)
+    }
+
+    /// the stored answer, `None` before the first update or if the last one had no current
+    /// price, as both store an answer of 0
+    pub fn previous_answer(&self) -> Option<u128> {
+        Some(self.answer).filter(|answer| *answer != 0)
+    }
+
+    /// adds the stored answer over the seconds since the last update to
+    /// `cumulative_price` and moves `timestamp` to `now`. Nothing accrues before
+    /// the first update or if the clock went backwards
+    pub fn accumulate(&mut self, now: i64) {
+        if self.timestamp != 0 && now > self.timestamp {
+            let elapsed = (now - self.timestamp) as u128;
+            self.cumulative_price = self.cumulative_price.wrapping_add(self.answer.wrapping_mul(elapsed));
+        }
+        self.timestamp = now;
+    }
+}
+
+/// time weighted average price between two snapshots of the same account,
+/// `None` unless `end` was taken after `start`
+pub fn twap(start: &PriceFeedAccount, end: &PriceFeedAccount) -> Option<u128> {
+    if end.timestamp <= start.timestamp {
+        return None;
+    }
+    let elapsed = (end.timestamp - start.timestamp) as u128;
+    Some(end.cumulative_price.wrapping_sub(start.cumulative_price) / elapsed)
+}
+
+pub fn get_price(
+    program_id: &Pubkey, // Owner of the config account
+    accounts: &[AccountInfo], // Public key of the account to read price data from
+    instruction_data: &[u8], // Ignored, has to be empty
+) -> ProgramResult {
+    msg!("Chainlink Solana Demo get_price");
+    check_no_price_instruction_data(instruction_data)?;
+
+    let accounts_iter = &mut accounts.iter();
+    // This is the account of our our account
+    let my_account = next_account_info(accounts_iter)?;
+    // This is the program's config, bounding how far the price may move
+    let max_deviation_bps = load_max_price_deviation(program_id, next_account_info(accounts_iter)?)?;
+    // This is the chainlink store program the feed is read through
+    let store_program = next_account_info(accounts_iter)?;
     // This is the account of the price feed data
     let feed_account = next_account_info(accounts_iter)?;
 
-    const DECIMALS: u32 = 9;
+    let price = chainlink::get_price(store_program, feed_account)?;
+    let feed_decimals = chainlink::get_decimals(store_program, feed_account).ok();
+    let decimals = resolve_decimals(feed_decimals.map(u32::from), None);
+    let decimals = u8::try_from(decimals).map_err(|_| {
+        msg!("Decimals {} are out of range", decimals);
+        ProgramError::InvalidArgument
+    })?;
+
+    store_price(my_account, price, decimals, max_deviation_bps)
+}
+
+/// Decimals to store a feed's answer with: the ones the feed reports if it could be read,
+/// else `override_decimals` given by the caller, else `PRICE_DECIMALS`
+pub fn resolve_decimals(feed_decimals: Option<u32>, override_decimals: Option<u32>) -> u32 {
+    feed_decimals
+        .or(override_decimals)
+        .unwrap_or_else(|| PRICE_DECIMALS.into())
+}
 
-    let price = chainlink::get_price(&chainlink::id(), feed_account)?;
+/// Reads every feed account after the price and config accounts and the chainlink store,
+/// and stores their median, so a single bad feed can't move the stored price.
+/// Fails with `PriceError::NoCurrentPrice`, keeping the stored price, if no feed has one
+pub fn get_median_price(
+    program_id: &Pubkey, // Owner of the config account
+    accounts: &[AccountInfo], // The price account, the config and the chainlink store, followed by the feeds to aggregate
+    instruction_data: &[u8], // Ignored, has to be empty
+) -> ProgramResult {
+    check_no_price_instruction_data(instruction_data)?;
+    let accounts_iter = &mut accounts.iter();
+    let my_account = next_account_info(accounts_iter)?;
+    let max_deviation_bps = load_max_price_deviation(program_id, next_account_info(accounts_iter)?)?;
+    let store_program = next_account_info(accounts_iter)?;
 
-    if let Some(price) = price {
-        let decimal = Decimal::new(price, DECIMALS);
-        msg!("Price is {}", decimal);
-    } else {
-        msg!("No current price");
+    let mut prices = Vec::new();
+    let mut decimals = None;
+    for feed_account in accounts_iter {
+        // answers are only comparable between feeds of the same decimals
+        let feed_decimals = chainlink::get_decimals(store_program, feed_account)?;
+        let expected_decimals = *decimals.get_or_insert(feed_decimals);
+        if feed_decimals != expected_decimals {
+            msg!("Feed {} has {} decimals, expected {}", feed_account.key, feed_decimals, expected_decimals);
+            return Err(ProgramError::InvalidArgument);
+        }
+        match chainlink::get_price(store_program, feed_account)? {
+            Some(price) => prices.push(price),
+            None => msg!("No current price from feed {}", feed_account.key),
+        }
     }
 
-     // Store the price ourselves
-     let mut price_data_account = PriceFeedAccount::try_from_slice(&my_account.data.borrow())?;
-     price_data_account.answer = price.unwrap_or(0);
-     price_data_account.serialize(&mut &mut my_account.data.borrow_mut()[..])?;
+    // without a single current price there is nothing to take the median of, and
+    // storing none would wipe the stored price
+    let (price, decimals) = match (median_price(&prices), decimals) {
+        (Some(price), Some(decimals)) => (price, decimals),
+        _ => {
+            msg!("None of the {} feeds has a current price", accounts.len().saturating_sub(3));
+            return Err(PriceError::NoCurrentPrice.into());
+        }
+    };
+    store_price(my_account, Some(price), decimals, max_deviation_bps)
+}
 
+/// Median of `prices`; for an even count the lower of the two middle prices,
+/// so the result is always a price one of the feeds reported
+pub fn median_price(prices: &[u128]) -> Option<u128> {
+    if prices.is_empty() {
+        return None;
+    }
+    let mut sorted = prices.to_vec();
+    sorted.sort_unstable();
+    Some(sorted[(sorted.len() - 1) / 2])
+}
 
+/// The price instructions take no data. The bound on how far a price may move used to be
+/// passed here, so data is rejected rather than silently ignored
+pub fn check_no_price_instruction_data(instruction_data: &[u8]) -> ProgramResult {
+    if !instruction_data.is_empty() {
+        msg!("The price instructions take no data, the max deviation is read from the config");
+        return Err(ProgramError::InvalidInstructionData);
+    }
     Ok(())
 }
 
+/// `Config::max_price_deviation_bps` of the program's config, which has to be the
+/// `CONFIG_SEED` PDA so only the config authority decides how far a price may move
+pub fn load_max_price_deviation(program_id: &Pubkey, config_account: &AccountInfo) -> std::result::Result<Option<u64>, ProgramError> {
+    let (config_key, _) = Pubkey::find_program_address(&[CONFIG_SEED], program_id);
+    if config_account.key != &config_key || config_account.owner != program_id {
+        msg!("Account {} is not the config {} of this program", config_account.key, config_key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let config = Config::try_deserialize(&mut &config_account.data.borrow()[..])?;
+    Ok(config.max_price_deviation_bps)
+}
+
+/// first code of `PriceError`, past those of `ChatError` so clients can tell them apart
+pub const PRICE_ERROR_OFFSET: u32 = 100;
 
-#[cfg(test)]
-mod test {
-    use {
-        super::*,
-        assert_matches::*,
-        solana_program::instruction::{AccountMeta, Instruction},
-        solana_program_test::*,
-        solana_sdk::{signature::Signer, transaction::Transaction},
+/// failures of the price instructions, returned to clients as `ProgramError::Custom(code)`
+/// with `PRICE_ERROR_OFFSET` plus the variant's position as the code
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PriceError {
+    /// the feed's decimals differ from the ones the stored answer was written with
+    DecimalsMismatch,
+    /// none of the feeds reported a current price
+    NoCurrentPrice,
+}
+
+impl From<PriceError> for ProgramError {
+    fn from(err: PriceError) -> Self {
+        ProgramError::Custom(PRICE_ERROR_OFFSET + err as u32)
+    }
+}
+
+/// Rejects an update whose `decimals` differ from the stored answer's, which would
+/// make the deviation check and `twap` compare differently scaled prices. Any
+/// decimals are accepted for the first update, before anything was stored
+pub fn check_price_decimals(stored: &PriceFeedAccount, decimals: u8) -> ProgramResult {
+    if stored.timestamp == 0 || stored.decimals == decimals {
+        return Ok(());
+    }
+    msg!("Feed has {} decimals, the stored price has {}", decimals, stored.decimals);
+    Err(PriceError::DecimalsMismatch.into())
+}
+
+/// Rejects `price` if it moved more than `max_deviation_bps` away from the `previous`
+/// answer, see `PriceFeedAccount::previous_answer`. Without a previous answer there is
+/// nothing to deviate from and any price is accepted. With one, a missing `price` is
+/// rejected, it would otherwise replace the stored answer with none
+pub fn check_price_deviation(previous: Option<u128>, price: Option<u128>, max_deviation_bps: u64) -> ProgramResult {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return Ok(()),
     };
+    let price = match price {
+        Some(price) => price,
+        None => {
+            msg!("No current price to replace {} with", previous);
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    let deviation = price.abs_diff(previous);
+    // deviation / previous > max_deviation_bps / 10_000, in 192 bits so nothing overflows
+    let scaled_deviation = U192::from(deviation).checked_mul(10_000u64.into());
+    let allowed = U192::from(previous).checked_mul(max_deviation_bps.into());
+    match (scaled_deviation, allowed) {
+        (Some(scaled_deviation), Some(allowed)) if scaled_deviation <= allowed => Ok(()),
+        _ => {
+            msg!("Price {} deviates more than {} bps from {}", price, max_deviation_bps, previous);
+            Err(ProgramError::InvalidArgument)
+        }
+    }
+}
 
-    #[tokio::test]
-    async fn test_transaction() {
-        let program_id = Pubkey::new_unique();
+/// A raw feed answer as the number it stands for, `answer / 10^decimals`
+pub fn answer_to_decimal(answer: u128, decimals: u32) -> Decimal {
+    Decimal::new(answer, decimals)
+}
 
-        let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
-            "bpf_program_template",
-            program_id,
-            processor!(process_instruction),
-        )
-        .start()
-        .await;
-
-        let mut transaction = Transaction::new_with_payer(
-            &[Instruction {
-                program_id,
-                accounts: vec![AccountMeta::new(payer.pubkey(), false)],
-                data: vec![1, 2, 3],
-            }],
-            Some(&payer.pubkey()),
-        );
-        transaction.sign(&[&payer], recent_blockhash);
+/// A signed feed answer as the number it stands for. Feeds such as spreads can report
+/// negative answers, which would wrap into huge values read as a `u128`
+pub fn signed_answer_to_decimal(answer: i128, decimals: u32) -> SignedDecimal {
+    SignedDecimal::new(answer, decimals)
+}
 
-        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+/// Log line for a feed answer, scaled by the feed's own `decimals`
+pub fn price_log(price: Option<u128>, decimals: u8) -> String {
+    match price {
+        Some(price) => format!("Price is {}", answer_to_decimal(price, decimals.into())),
+        None => String::from("No current price"),
     }
 }
 
+/// Logs `price` and stores it in `my_account` with the feed's `decimals`, 0 when
+/// there is no current price. With `max_deviation_bps` set in the config, a price too far
+/// from the stored one is rejected and the stored price is kept. A feed with other decimals
+/// than the stored price is always rejected, see `check_price_decimals`.
+fn store_price(my_account: &AccountInfo, price: Option<u128>, decimals: u8, max_deviation_bps: Option<u64>) -> ProgramResult {
+    msg!("{}", price_log(price, decimals));
+
+     // Store the price ourselves
+     let mut price_data_account = PriceFeedAccount::deserialize_versioned(&my_account.data.borrow())?;
+     check_price_decimals(&price_data_account, decimals)?;
+     if let Some(max_deviation_bps) = max_deviation_bps {
+         check_price_deviation(price_data_account.previous_answer(), price, max_deviation_bps)?;
+     }
+     price_data_account.accumulate(Clock::get()?.unix_timestamp);
+     price_data_account.answer = price.unwrap_or(0);
+     price_data_account.decimals = decimals;
+     price_data_account.serialize(&mut &mut my_account.data.borrow_mut()[..])?;
+
+
+    Ok(())
+}
+
 
 // Sanity tests
 #[cfg(test)]
 mod test {
     use super::*;
-    use solana_program::clock::Epoch;
+    use anchor_spl::token::spl_token;
+    use solana_program::{ bpf_loader, clock::Epoch, entrypoint::{ deserialize, SUCCESS }, instruction::Instruction, program_pack::Pack, program_stubs };
+    use std::{ cell::RefCell, collections::{ BTreeMap, BTreeSet }, sync::Once };
     //use std::mem;
 
-    #[test]
-    fn test_sanity() {
-        let program_id = Pubkey::default();
-        let key = Pubkey::default();
-        let mut lamports = 0;
-        let messages = get_init_chat_messages(); 
+    fn chat_message(archive_id: &str, created_on: &str) -> ChatMessage {
+        ChatMessage {
+            archive_id: String::from(archive_id),
+            created_on: String::from(created_on),
+            sender: Pubkey::default(),
+            inline_text: None,
+        }
+    }
+
+    fn add_message(nonce: u64, message: ChatMessage) -> Vec<u8> {
+        ChatInstruction::AddMessage { nonce, message }.try_to_vec().unwrap()
+    }
+
+    /// account data holding `messages` and `nonce`, exactly as long as a write of them
+    fn chat_account_data(messages: &[ChatMessage], nonce: u64) -> Vec<u8> {
         let mut data = messages.try_to_vec().unwrap();
-        let owner = Pubkey::default();
-        let account = AccountInfo::new(
-            &key,
-            false,
-            true,
-            &mut lamports,
-            &mut data,
-            &owner,
-            false,
-            Epoch::default(),
-        );
-        
-        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
-        let created_on = "0001621449453837";
-        let instruction_data_chat_message = ChatMessage{ archive_id: String::from(archive_id), created_on: String::from(created_on) };
-        let instruction_data = instruction_data_chat_message.try_to_vec().unwrap();
+        data.extend_from_slice(&nonce.to_le_bytes());
+        data
+    }
 
-        let accounts = vec![account];
+    fn inline_message(text: &str, created_on: &str) -> ChatMessage {
+        ChatMessage { inline_text: Some(String::from(text)), ..chat_message(DUMMY_TX_ID, created_on) }
+    }
 
-        process_instruction(&program_id, &accounts, &instruction_data).unwrap();
-        let chat_messages = &<Vec<ChatMessage>>::try_from_slice(&accounts[0].data.borrow())
-        .unwrap()[0];
-        let test_archive_id = &chat_messages.archive_id;
-        let test_created_on = &chat_messages.created_on;
-        println!("chat message {:?}", &chat_messages);
-        // I added first data and expect it to contain the given data
-        assert_eq!(
-            String::from(archive_id).eq(test_archive_id),
-            true
-        );
-        assert_eq!(
-            String::from(created_on).eq(test_created_on),
-            true
-        );
+    /// the key, lamports, data and owner of an account, owned by the test so the
+    /// `AccountInfo` lent out by `info` can borrow them
+    struct TestAccount {
+        key: Pubkey,
+        is_signer: bool,
+        is_writable: bool,
+        lamports: u64,
+        data: Vec<u8>,
+        owner: Pubkey,
+        executable: bool,
+    }
+
+    impl TestAccount {
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                self.is_writable,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                self.executable,
+                Epoch::default(),
+            )
+        }
+    }
+
+    /// a signing, writable account of a new key holding `data` and funded to be rent exempt
+    fn make_account(data: Vec<u8>, owner: &Pubkey) -> TestAccount {
+        TestAccount {
+            key: Pubkey::new_unique(),
+            is_signer: true,
+            is_writable: true,
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: *owner,
+            executable: false,
+        }
+    }
+
+    /// unix time of the clock in `TestSyscallStubs`
+    const NOW: i64 = 1_621_449_500;
+
+    /// what the stubbed chainlink store answers for a feed account holding it
+    #[derive(BorshSerialize, BorshDeserialize)]
+    struct TestFeed {
+        round_id: u32,
+        decimals: u8,
+        answer: i128,
+    }
+
+    fn feed_account(round_id: u32, decimals: u8, answer: i128) -> TestAccount {
+        make_account(TestFeed { round_id, decimals, answer }.try_to_vec().unwrap(), &chainlink::ID)
+    }
+
+    fn store_program() -> TestAccount {
+        TestAccount { key: chainlink::ID, is_signer: false, executable: true, ..make_account(vec![], &bpf_loader::ID) }
+    }
+
+    thread_local! {
+        /// return data of the last CPI on this test's thread
+        static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+    }
+
+    /// stands in for the runtime: a clock at `NOW`, the default rent, system transfers, and
+    /// a chainlink store answering the queries of `chainlink_solana` from the `TestFeed` in
+    /// the feed account
+    struct TestSyscallStubs;
+
+    impl program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_invoke_signed(&self, instruction: &Instruction, account_infos: &[AccountInfo], _signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+            let info = |key: &Pubkey| account_infos.iter().find(|info| info.key == key).unwrap();
+            if system_program::check_id(&instruction.program_id) {
+                // only transfers, the u32 instruction 2 followed by the lamports
+                assert_eq!(instruction.data[..4], 2u32.to_le_bytes());
+                let lamports = u64::from_le_bytes(<[u8; 8]>::try_from(&instruction.data[4..12]).unwrap());
+                **info(&instruction.accounts[0].pubkey).try_borrow_mut_lamports()? -= lamports;
+                **info(&instruction.accounts[1].pubkey).try_borrow_mut_lamports()? += lamports;
+                return Ok(());
+            }
+            assert_eq!(instruction.program_id, chainlink::ID);
+            let feed = TestFeed::try_from_slice(&info(&instruction.accounts[0].pubkey).data.borrow()).unwrap();
+            // the query variant follows the 8 byte instruction discriminator
+            let answer = match instruction.data[8] {
+                1 => feed.decimals.try_to_vec(),
+                4 => chainlink_solana::Round { round_id: feed.round_id, slot: 0, timestamp: 0, answer: feed.answer }.try_to_vec(),
+                query => panic!("Unexpected chainlink query {}", query),
+            };
+            RETURN_DATA.with(|data| *data.borrow_mut() = Some((chainlink::ID, answer.unwrap())));
+            Ok(())
+        }
+
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Clock) = Clock { unix_timestamp: NOW, ..Clock::default() } };
+            SUCCESS
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            SUCCESS
+        }
+
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            RETURN_DATA.with(|data| data.borrow().clone())
+        }
+    }
+
+    /// installs `TestSyscallStubs` for the whole test binary, they only add to the default stubs
+    fn install_syscall_stubs() {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs));
+        });
+    }
+
+    /// `accounts` serialized the way the runtime hands them to a program, each account's data
+    /// followed by room to grow by `MAX_PERMITTED_DATA_INCREASE`, for `runtime_accounts`.
+    /// kept in u64s so the accounts are aligned like on chain
+    fn runtime_input(accounts: &[TestAccount]) -> Vec<u64> {
+        let mut input = (accounts.len() as u64).to_le_bytes().to_vec();
+        for account in accounts {
+            input.extend_from_slice(&[u8::MAX, account.is_signer as u8, account.is_writable as u8, account.executable as u8]);
+            // the original data length, filled in by `deserialize`
+            input.extend_from_slice(&[0; 4]);
+            input.extend_from_slice(account.key.as_ref());
+            input.extend_from_slice(account.owner.as_ref());
+            input.extend_from_slice(&account.lamports.to_le_bytes());
+            input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            input.extend_from_slice(&account.data);
+            input.resize((input.len() + MAX_PERMITTED_DATA_INCREASE).div_ceil(8) * 8, 0);
+            input.extend_from_slice(&Epoch::default().to_le_bytes());
+        }
+        // no instruction data, then the program id
+        input.extend_from_slice(&0u64.to_le_bytes());
+        input.extend_from_slice(ID.as_ref());
+        input.resize(input.len().div_ceil(8) * 8, 0);
+        input.chunks(8).map(|word| u64::from_le_bytes(<[u8; 8]>::try_from(word).unwrap())).collect()
+    }
+
+    /// the accounts of `runtime_input`, which unlike `TestAccount::info` can `realloc`
+    fn runtime_accounts(input: &mut [u64]) -> Vec<AccountInfo<'_>> {
+        let (_, accounts, _) = unsafe { deserialize(input.as_mut_ptr() as *mut u8) };
+        accounts
+    }
+
+    /// the price account, the program's config and the chainlink store, followed by `feeds`,
+    /// for `get_price` or `get_median_price`
+    fn price_accounts(stored: &PriceFeedAccount, max_price_deviation_bps: Option<u64>, feeds: Vec<TestAccount>) -> Vec<TestAccount> {
+        let (config_key, _) = Pubkey::find_program_address(&[CONFIG_SEED], &ID);
+        let config = Config { authority: Pubkey::new_unique(), max_deposit: 0, paused: false, max_price_deviation_bps };
+        let mut accounts = vec![
+            make_account(stored.try_to_vec().unwrap(), &ID),
+            TestAccount { key: config_key, ..make_account(anchor_account_data(&config), &ID) },
+            store_program(),
+        ];
+        accounts.extend(feeds);
+        accounts
+    }
+
+    /// data of an anchor account holding `account`, discriminator first
+    fn anchor_account_data<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    /// an initialized SPL token mint owned by the token program
+    fn mint_account(key: Pubkey, mint_authority: Pubkey) -> TestAccount {
+        let mint = spl_token::state::Mint {
+            mint_authority: COption::Some(mint_authority),
+            decimals: 6,
+            is_initialized: true,
+            ..spl_token::state::Mint::default()
+        };
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint::pack(mint, &mut data).unwrap();
+        TestAccount { key, ..make_account(data, &token::ID) }
+    }
+
+    /// an initialized SPL token account of `mint` held by `owner`
+    fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> TestAccount {
+        let account = spl_token::state::Account {
+            mint,
+            owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..spl_token::state::Account::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(account, &mut data).unwrap();
+        make_account(data, &token::ID)
+    }
+
+    /// the accounts of a `Deposit` or `Redeem` by a new user, in the order of their fields,
+    /// after the leading `config` or `pool_fees`
+    fn user_accounts(first: TestAccount) -> Vec<TestAccount> {
+        let authority = Pubkey::new_unique();
+        let usdc_mint = Pubkey::new_unique();
+        let dog_money_mint = Pubkey::new_unique();
+        let (program_signer, _) = Pubkey::find_program_address(&[usdc_mint.as_ref()], &ID);
+        let (program_vault, _) = Pubkey::find_program_address(&[VAULT_SEED, usdc_mint.as_ref()], &ID);
+        let (user_data, _) = Pubkey::find_program_address(&[USER_DATA_SEED, authority.as_ref(), usdc_mint.as_ref()], &ID);
+        vec![
+            first,
+            TestAccount { key: program_signer, ..make_account(Vec::new(), &system_program::id()) },
+            TestAccount { key: user_data, ..make_account(anchor_account_data(&UserData::default()), &ID) },
+            TestAccount { key: authority, ..make_account(Vec::new(), &system_program::id()) },
+            mint_account(usdc_mint, Pubkey::new_unique()),
+            token_account(usdc_mint, authority, 1_000),
+            TestAccount { key: program_vault, ..token_account(usdc_mint, program_signer, 0) },
+            mint_account(dog_money_mint, program_signer),
+            token_account(dog_money_mint, authority, 0),
+            TestAccount { key: token::ID, executable: true, ..make_account(Vec::new(), &Pubkey::default()) },
+        ]
+    }
+
+    #[test]
+    fn test_transaction() {
+        let program_id = Pubkey::new_unique();
+        // a system account, which the chat handler refuses to write to
+        let mut test_payer = make_account(Vec::new(), &system_program::id());
+        let payer = test_payer.info();
+        assert_eq!(process_instruction(&program_id, &[payer], &[1, 2, 3]), Err(ChatError::NotOwner.into()));
+    }
+
+    #[test]
+    fn test_pool_fees_round_trip() {
+        let fees = Fees {
+            admin_trade_fee_numerator: 1,
+            admin_trade_fee_denominator: 2,
+            admin_withdraw_fee_numerator: 3,
+            admin_withdraw_fee_denominator: 4,
+            trade_fee_numerator: 5,
+            trade_fee_denominator: 1000,
+            withdraw_fee_numerator: 7,
+            withdraw_fee_denominator: 1000,
+        };
+        let stored = PoolFees::from(fees);
+        let data = stored.try_to_vec().unwrap();
+        assert_eq!(data.len(), 64);
+        let decoded = PoolFees::try_from_slice(&data).unwrap();
+        assert_eq!(decoded, stored);
+
+        let restored = Fees::from(decoded);
+        assert_eq!(restored, fees);
+        assert_eq!(restored.trade_fee(100_000), Some(500));
+        assert_eq!(restored.admin_trade_fee(500), Some(250));
+        assert_eq!(restored.withdraw_fee(100_000), Some(700));
+        assert_eq!(restored.admin_withdraw_fee(700), Some(525));
+    }
+
+    #[test]
+    fn test_deposit_cap() {
+        let max_deposit = 1_000_000;
+        assert_eq!(check_deposit_cap(max_deposit, 0, max_deposit).unwrap(), max_deposit);
+        assert!(matches!(
+            check_deposit_cap(max_deposit, 0, max_deposit + 1),
+            Err(ErrorCode::DepositCapExceeded)
+        ));
+
+        // cumulative across two deposits
+        let total = check_deposit_cap(max_deposit, 0, 600_000).unwrap();
+        assert_eq!(check_deposit_cap(max_deposit, total, 400_000).unwrap(), max_deposit);
+        assert!(matches!(
+            check_deposit_cap(max_deposit, total, 400_001),
+            Err(ErrorCode::DepositCapExceeded)
+        ));
+        assert!(matches!(
+            check_deposit_cap(u64::MAX, u64::MAX, 1),
+            Err(ErrorCode::DepositCapExceeded)
+        ));
+    }
+
+    #[test]
+    fn test_pause() {
+        assert!(matches!(check_not_paused(true), Err(ErrorCode::Paused)));
+        assert!(check_not_paused(false).is_ok());
+    }
+
+    #[test]
+    fn test_deposit_config_pinned() {
+        let (config_key, _) = Pubkey::find_program_address(&[CONFIG_SEED], &ID);
+        let paused = Config { authority: Pubkey::new_unique(), max_deposit: u64::MAX, paused: true, max_price_deviation_bps: None };
+        let validate = |accounts: &mut Vec<TestAccount>| -> Result<()> {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            let deposit = Deposit::try_accounts(&ID, &mut &infos[..], &[], &mut BTreeMap::new(), &mut BTreeSet::new())?;
+            deposit_outcome(&deposit.config, deposit.user_data.total_deposited, 1)?;
+            Ok(())
+        };
+
+        // the program's config is paused
+        let config = TestAccount { key: config_key, ..make_account(anchor_account_data(&paused), &ID) };
+        assert_eq!(validate(&mut user_accounts(config)), Err(ErrorCode::Paused.into()));
+
+        // so a config of the depositor's own, unpaused, is refused
+        let foreign = Config { paused: false, ..paused };
+        let config = make_account(anchor_account_data(&foreign), &ID);
+        assert_eq!(
+            validate(&mut user_accounts(config)),
+            Err(anchor_lang::error::ErrorCode::ConstraintSeeds.into())
+        );
+    }
+
+    #[test]
+    fn test_redeem_pool_fees_pinned() {
+        let (pool_fees_key, _) = Pubkey::find_program_address(&[POOL_FEES_SEED], &ID);
+        let pool_fees = PoolFeesAccount { admin: Pubkey::new_unique(), fees: PoolFees::default() };
+        let validate = |accounts: &mut Vec<TestAccount>| -> Result<()> {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            Redeem::try_accounts(&ID, &mut &infos[..], &[], &mut BTreeMap::new(), &mut BTreeSet::new())?;
+            Ok(())
+        };
+
+        let stored = TestAccount { key: pool_fees_key, ..make_account(anchor_account_data(&pool_fees), &ID) };
+        assert_eq!(validate(&mut user_accounts(stored)), Ok(()));
+
+        // fees of the redeemer's own choosing, say none at all, are refused
+        let foreign = make_account(anchor_account_data(&pool_fees), &ID);
+        assert_eq!(
+            validate(&mut user_accounts(foreign)),
+            Err(anchor_lang::error::ErrorCode::ConstraintSeeds.into())
+        );
+    }
+
+    #[test]
+    fn test_deposit_vault_pinned() {
+        let (config_key, _) = Pubkey::find_program_address(&[CONFIG_SEED], &ID);
+        let config = Config { authority: Pubkey::new_unique(), max_deposit: u64::MAX, paused: false, max_price_deviation_bps: None };
+        let accounts = || {
+            user_accounts(TestAccount { key: config_key, ..make_account(anchor_account_data(&config), &ID) })
+        };
+        let validate = |accounts: &mut Vec<TestAccount>| -> Result<()> {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            Deposit::try_accounts(&ID, &mut &infos[..], &[], &mut BTreeMap::new(), &mut BTreeSet::new())?;
+            Ok(())
+        };
+        assert_eq!(validate(&mut accounts()), Ok(()));
+
+        // paying worthless tokens into an account of the depositor's own
+        let mut own_vault = accounts();
+        let worthless = Pubkey::new_unique();
+        own_vault[5] = token_account(worthless, own_vault[3].key, 1_000);
+        own_vault[6] = token_account(worthless, own_vault[3].key, 0);
+        assert_eq!(validate(&mut own_vault), Err(anchor_lang::error::ErrorCode::ConstraintRaw.into()));
+        let mut own_vault = accounts();
+        own_vault[6] = token_account(own_vault[4].key, own_vault[3].key, 0);
+        assert_eq!(validate(&mut own_vault), Err(anchor_lang::error::ErrorCode::ConstraintSeeds.into()));
+
+        // minting for a signer that isn't the USDC mint's PDA
+        let mut foreign_signer = accounts();
+        foreign_signer[1].key = Pubkey::new_unique();
+        assert_eq!(validate(&mut foreign_signer), Err(anchor_lang::error::ErrorCode::ConstraintSeeds.into()));
+
+        // into an account of another mint than the dog money
+        let mut other_mint = accounts();
+        other_mint[8] = token_account(Pubkey::new_unique(), other_mint[3].key, 0);
+        assert_eq!(validate(&mut other_mint), Err(anchor_lang::error::ErrorCode::ConstraintRaw.into()));
+    }
+
+    #[test]
+    fn test_redeem_vault_pinned() {
+        let (pool_fees_key, _) = Pubkey::find_program_address(&[POOL_FEES_SEED], &ID);
+        let pool_fees = PoolFeesAccount { admin: Pubkey::new_unique(), fees: PoolFees::default() };
+        let accounts = || {
+            user_accounts(TestAccount { key: pool_fees_key, ..make_account(anchor_account_data(&pool_fees), &ID) })
+        };
+        let validate = |accounts: &mut Vec<TestAccount>| -> Result<()> {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            Redeem::try_accounts(&ID, &mut &infos[..], &[], &mut BTreeMap::new(), &mut BTreeSet::new())?;
+            Ok(())
+        };
+        assert_eq!(validate(&mut accounts()), Ok(()));
+
+        // a USDC account of the program signer's other than the vault
+        let mut foreign_vault = accounts();
+        foreign_vault[6] = token_account(foreign_vault[4].key, foreign_vault[1].key, u64::MAX);
+        assert_eq!(validate(&mut foreign_vault), Err(anchor_lang::error::ErrorCode::ConstraintSeeds.into()));
+
+        // the vault's address, but not held by the program signer
+        let mut not_held = accounts();
+        not_held[6] = TestAccount { key: not_held[6].key, ..token_account(not_held[4].key, not_held[3].key, 0) };
+        assert_eq!(validate(&mut not_held), Err(anchor_lang::error::ErrorCode::ConstraintTokenOwner.into()));
+
+        // a signer that isn't the USDC mint's PDA
+        let mut foreign_signer = accounts();
+        foreign_signer[1].key = Pubkey::new_unique();
+        assert_eq!(validate(&mut foreign_signer), Err(anchor_lang::error::ErrorCode::ConstraintSeeds.into()));
+
+        // paid out to an account of another mint
+        let mut other_mint = accounts();
+        other_mint[5] = token_account(Pubkey::new_unique(), other_mint[3].key, 1_000);
+        assert_eq!(validate(&mut other_mint), Err(anchor_lang::error::ErrorCode::ConstraintRaw.into()));
+    }
+
+    #[test]
+    fn test_backing_vault_pinned() {
+        let (pool_fees_key, _) = Pubkey::find_program_address(&[POOL_FEES_SEED], &ID);
+        let pool_fees = PoolFeesAccount { admin: Pubkey::new_unique(), fees: PoolFees::default() };
+        let check = |accounts: &mut Vec<TestAccount>| -> Result<()> {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            let redeem = Redeem::try_accounts(&ID, &mut &infos[..], &[], &mut BTreeMap::new(), &mut BTreeSet::new())?;
+            check_backing(redeem.dog_money_mint.supply, redeem.program_vault.amount)?;
+            Ok(())
+        };
+        // 1 USDC in the vault backing the dog money of 2
+        let mut accounts = user_accounts(TestAccount { key: pool_fees_key, ..make_account(anchor_account_data(&pool_fees), &ID) });
+        let mut mint = spl_token::state::Mint::unpack(&accounts[7].data).unwrap();
+        mint.supply = 2 * DOG_MONEY_PER_USDC;
+        spl_token::state::Mint::pack(mint, &mut accounts[7].data).unwrap();
+        accounts[6] = TestAccount { key: accounts[6].key, ..token_account(accounts[4].key, accounts[1].key, 1) };
+        assert_eq!(check(&mut accounts), Err(ErrorCode::BackingMismatch.into()));
+
+        // a well funded USDC account of the program signer can't stand in for the vault
+        accounts[6] = token_account(accounts[4].key, accounts[1].key, u64::MAX);
+        assert_eq!(check(&mut accounts), Err(anchor_lang::error::ErrorCode::ConstraintSeeds.into()));
+    }
+
+    #[test]
+    fn test_deposit_outcome() {
+        let mut config = Config { authority: Pubkey::new_unique(), max_deposit: 1_000_000, paused: false, max_price_deviation_bps: None };
+        // what simulate_deposit returns for a user's deposits so far is what deposit records and mints
+        assert_eq!(deposit_outcome(&config, 0, 250_000).unwrap(), (250_000, 250_000_000));
+        assert_eq!(deposit_outcome(&config, 250_000, 750_000).unwrap(), (1_000_000, 750_000_000));
+        assert!(matches!(deposit_outcome(&config, 250_000, 750_001), Err(ErrorCode::DepositCapExceeded)));
+
+        config.max_deposit = u64::MAX;
+        assert!(matches!(deposit_outcome(&config, 0, u64::MAX), Err(ErrorCode::MathOverflow)));
+        assert_eq!(
+            deposit_outcome(&config, 0, u64::MAX / DOG_MONEY_PER_USDC).unwrap().1,
+            u64::MAX / DOG_MONEY_PER_USDC * DOG_MONEY_PER_USDC
+        );
+
+        config.paused = true;
+        assert!(matches!(deposit_outcome(&config, 0, 1), Err(ErrorCode::Paused)));
+    }
+
+    #[test]
+    fn test_accrued_amount() {
+        // 10% a year, in rate per second
+        let rate_per_sec = 3_170;
+        let year = 365 * 24 * 60 * 60;
+        assert_eq!(accrued_amount(1_000_000, rate_per_sec, 0), Some(0));
+        assert_eq!(accrued_amount(0, rate_per_sec, year), Some(0));
+        assert_eq!(accrued_amount(1_000_000_000, rate_per_sec, year), Some(99_969_120));
+        assert_eq!(accrued_amount(1_000_000_000, rate_per_sec, 60), Some(190));
+        assert_eq!(accrued_amount(1_000_000, rate_per_sec, -1), None);
+
+        // principal * rate * elapsed past u128, and a yield past u64
+        assert_eq!(accrued_amount(u64::MAX, u64::MAX, i64::MAX), None);
+        assert_eq!(accrued_amount(u64::MAX, YIELD_RATE_DENOMINATOR as u64, 2), None);
+        assert_eq!(accrued_amount(u64::MAX, YIELD_RATE_DENOMINATOR as u64, 1), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_redeem_outcome() {
+        // 0.10% withdraw fee
+        let fees = Fees::from_bps(30, 0, 10, 0);
+        let (burned, net_usdc, fee) = redeem_outcome(250_000_000, &fees).unwrap();
+        assert_eq!(burned, 250_000_000);
+        assert_eq!(fee, fees.withdraw_fee(250_000).unwrap());
+        assert_eq!(fee, 250);
+        assert_eq!(net_usdc, 250_000 - fee);
+
+        // dog money short of a whole USDC unit isn't burned
+        assert_eq!(redeem_outcome(1_999, &fees).unwrap(), (1_000, 1, 0));
+        assert_eq!(redeem_outcome(999, &fees).unwrap(), (0, 0, 0));
+        assert_eq!(redeem_outcome(u64::MAX, &Fees::from_bps(0, 0, 0, 0)).unwrap().1, u64::MAX / DOG_MONEY_PER_USDC);
+
+        // a redeem pays back what the deposit minted for, less the fee
+        let config = Config { authority: Pubkey::new_unique(), max_deposit: 1_000_000, paused: false, max_price_deviation_bps: None };
+        let (_, minted) = deposit_outcome(&config, 0, 250_000).unwrap();
+        assert_eq!(redeem_outcome(minted, &fees).unwrap(), (minted, 249_750, 250));
+
+        let misconfigured = Fees { withdraw_fee_denominator: 0, ..fees };
+        assert!(matches!(redeem_outcome(minted, &misconfigured), Err(ErrorCode::MathOverflow)));
+    }
+
+    #[test]
+    fn test_check_backing() {
+        let config = Config { authority: Pubkey::new_unique(), max_deposit: 1_000_000, paused: false, max_price_deviation_bps: None };
+        let (_, minted) = deposit_outcome(&config, 0, 250_000).unwrap();
+        let vault = 250_000;
+        assert!(check_backing(minted, vault).is_ok());
+        assert!(check_backing(0, 0).is_ok());
+
+        // a redeem keeps it backed, with the fee as surplus
+        let fees = Fees::from_bps(30, 0, 10, 0);
+        let (burned, net_usdc, _) = redeem_outcome(100_000_999, &fees).unwrap();
+        assert!(check_backing(minted - burned, vault - net_usdc).is_ok());
+
+        // tampered supply or a drained vault
+        assert!(matches!(check_backing(minted + 1, vault), Err(ErrorCode::BackingMismatch)));
+        assert!(matches!(check_backing(minted, vault - 1), Err(ErrorCode::BackingMismatch)));
+        assert!(matches!(check_backing(1, 0), Err(ErrorCode::BackingMismatch)));
+        assert!(check_backing(u64::MAX, u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_dog_money_price_in_usdc() {
+        let config = Config { authority: Pubkey::new_unique(), max_deposit: 1_000_000, paused: false, max_price_deviation_bps: None };
+        let (_, minted) = deposit_outcome(&config, 0, 250_000).unwrap();
+        let genesis = dog_money_price_in_usdc(250_000, minted, 6).unwrap();
+        assert_eq!(genesis, Decimal::new(1_000, 6));
+        assert_eq!(genesis.to_string(), "0.001000");
+        assert_eq!(dog_money_price_in_usdc(1, 1_000, 3).unwrap().to_string(), "0.001");
+
+        // withdraw fees left in the vault raise the price
+        assert_eq!(dog_money_price_in_usdc(250_250, minted, 6), Some(Decimal::new(1_001, 6)));
+        // rounded down
+        assert_eq!(dog_money_price_in_usdc(1, 3, 2), Some(Decimal::new(33, 2)));
+
+        assert_eq!(dog_money_price_in_usdc(250_000, 0, 6), None);
+        assert_eq!(dog_money_price_in_usdc(0, 0, 6), None);
+        assert_eq!(dog_money_price_in_usdc(1, 1, 40), None);
+    }
+
+    /// spl token account data holding `amount`
+    fn token_account_data(amount: u64) -> Vec<u8> {
+        let mut data = vec![0; 165];
+        data[64..72].copy_from_slice(&amount.to_le_bytes());
+        data[108] = 1; // initialized
+        data
+    }
+
+    #[test]
+    fn test_reserves_from_token_accounts() {
+        let source = token_account_data(1_000_000_000);
+        let destination = token_account_data(42_000_000);
+        assert_eq!(reserves_from_token_accounts(&source, &destination), Ok((1_000_000_000, 42_000_000)));
+        assert_eq!(reserves_from_token_accounts(&destination, &source), Ok((42_000_000, 1_000_000_000)));
+
+        assert!(reserves_from_token_accounts(&source, &destination[..64]).is_err());
+        assert!(reserves_from_token_accounts(&[0; 165], &destination).is_err());
+    }
+
+    #[test]
+    fn test_config_authority() {
+        let authority = Pubkey::new_unique();
+        let new_authority = Pubkey::new_unique();
+        let mut config = Config { authority, max_deposit: 0, paused: false, max_price_deviation_bps: None };
+
+        assert!(check_authority(&config.authority, &authority).is_ok());
+        assert_eq!(
+            check_authority(&config.authority, &new_authority),
+            Err(ErrorCode::Unauthorized.into())
+        );
+
+        // hand off to the new authority
+        config.authority = new_authority;
+        assert_eq!(
+            check_authority(&config.authority, &authority),
+            Err(ErrorCode::Unauthorized.into())
+        );
+        assert!(check_authority(&config.authority, &new_authority).is_ok());
+
+        // the pool fees admin is checked the same way
+        let pool_fees = PoolFeesAccount { admin: authority, fees: PoolFees::default() };
+        assert!(check_authority(&pool_fees.admin, &authority).is_ok());
+        assert_eq!(
+            check_authority(&pool_fees.admin, &new_authority),
+            Err(ErrorCode::Unauthorized.into())
+        );
+    }
+
+    #[test]
+    fn test_check_token_program() {
+        let token_program_id = token::ID;
+        let bogus_id = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let mut test_token_program = TestAccount { key: token_program_id, is_signer: false, is_writable: false, lamports: 0, data: Vec::new(), owner, executable: true };
+        let token_program = test_token_program.info();
+        assert!(check_token_program(&token_program).is_ok());
+
+        let mut test_bogus = TestAccount { key: bogus_id, is_signer: false, is_writable: false, lamports: 0, data: Vec::new(), owner, executable: true };
+        let bogus = test_bogus.info();
+        assert_eq!(check_token_program(&bogus), Err(ErrorCode::InvalidTokenProgram.into()));
+
+        let mut not_executable = token_program.clone();
+        not_executable.executable = false;
+        assert_eq!(check_token_program(&not_executable), Err(ErrorCode::InvalidTokenProgram.into()));
+    }
+
+    #[test]
+    fn test_chat_message_schema() {
+        use borsh::schema::{BorshSchemaContainer, Definition, Fields};
+
+        let container = BorshSchemaContainer::try_from_slice(&chat_messages_schema()).unwrap();
+        assert_eq!(container.declaration, "Vec<ChatMessage>");
+
+        let fields = match container.definitions.get("ChatMessage") {
+            Some(Definition::Struct { fields: Fields::NamedFields(fields) }) => fields,
+            _ => panic!("ChatMessage is not described as a struct"),
+        };
+        let archive_message = chat_message("abcdefghijabcdefghijabcdefghijabcdefghijabc", "0001621449453837");
+        let inline = inline_message("gm", "0001621449453837");
+        for message in [archive_message, inline].iter() {
+            let encoded = message.try_to_vec().unwrap();
+            // strings are encoded as a u32 length followed by the bytes, pubkeys as
+            // their 32 bytes and options as a one byte tag followed by the value if present
+            let described_len: usize = fields
+                .iter()
+                .map(|(name, declaration)| match name.as_str() {
+                    "archive_id" => {
+                        assert_eq!(declaration, "string");
+                        4 + message.archive_id.len()
+                    }
+                    "created_on" => {
+                        assert_eq!(declaration, "string");
+                        4 + message.created_on.len()
+                    }
+                    "sender" => {
+                        assert_eq!(declaration, "Pubkey");
+                        32
+                    }
+                    "inline_text" => {
+                        assert_eq!(declaration, "Option<string>");
+                        1 + message.inline_text.as_ref().map_or(0, |text| 4 + text.len())
+                    }
+                    _ => panic!("unexpected field {}", name),
+                })
+                .sum();
+            assert_eq!(described_len, encoded.len());
+        }
+    }
+
+    #[test]
+    fn test_is_dummy_slot() {
+        assert!(is_dummy_slot(&get_init_chat_message()));
+        assert!(!is_dummy_slot(&chat_message("abcdefghijabcdefghijabcdefghijabcdefghijabc", "0001621449453837")));
+
+        // near-dummy ids
+        assert!(is_dummy_slot(&chat_message(" 0000000000000000000000000000000000000000000\n", DUMMY_CREATED_ON)));
+        assert!(is_dummy_slot(&chat_message("0000", DUMMY_CREATED_ON)));
+        assert!(is_dummy_slot(&chat_message("", DUMMY_CREATED_ON)));
+        assert!(!is_dummy_slot(&chat_message("000000000000000000000000000000000000000000O", DUMMY_CREATED_ON)));
+        assert!(!is_dummy_slot(&chat_message("0000000000000000000000 000000000000000000000", DUMMY_CREATED_ON)));
+
+        // inline messages don't need an arweave tx
+        assert!(!is_dummy_slot(&inline_message("gm", DUMMY_CREATED_ON)));
+        assert!(!is_dummy_slot(&inline_message("", DUMMY_CREATED_ON)));
+    }
+
+    #[test]
+    fn test_inline_text() {
+        let program_id = Pubkey::default();
+        let key = Pubkey::default();
+        let owner = Pubkey::default();
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let longest = "a".repeat(MAX_INLINE_TEXT_LEN);
+        assert_eq!(inline_message(&longest, "0001621449453837").try_to_vec().unwrap().len(), MAX_CHAT_MESSAGE_LEN);
+
+        // a zeroed account sized for inline text holds archive-only and inline messages side by side
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: vec![0; CHAT_MESSAGES_ACCOUNT_LEN], owner, executable: false };
+        let account = test_account.info();
+        let accounts = vec![account];
+        let writes = vec![
+            chat_message(archive_id, "0001621449453837"),
+            inline_message(&longest, "0001621449453838"),
+            inline_message("gm", "0001621449453839"),
+            chat_message(archive_id, "0001621449453840"),
+        ];
+        for (nonce, message) in (1..).zip(writes) {
+            process_instruction(&program_id, &accounts, &add_message(nonce, message)).unwrap();
+        }
+        let messages = unpack_chat_messages(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(messages.len(), CHAT_MESSAGE_SLOTS);
+        assert_eq!(count_active_messages(&messages), 4);
+        assert_eq!(messages[0].archive_id, archive_id);
+        assert_eq!(messages[0].inline_text, None);
+        assert_eq!(messages[1].inline_text.as_deref(), Some(longest.as_str()));
+        assert_eq!(messages[2].inline_text.as_deref(), Some("gm"));
+        assert_eq!(messages[3].created_on, "0001621449453840");
+
+        // one byte over the limit is rejected and leaves the account untouched
+        let before = accounts[0].data.borrow().to_vec();
+        let oversized = inline_message(&"a".repeat(MAX_INLINE_TEXT_LEN + 1), "0001621449453841");
+        assert_eq!(check_inline_text(&oversized), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &add_message(5, oversized)),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(*accounts[0].data.borrow(), &before[..]);
+
+        // every slot filled with the longest inline text fits exactly
+        let full: Vec<ChatMessage> = (0..CHAT_MESSAGE_SLOTS).map(|_| inline_message(&longest, "0001621449453837")).collect();
+        assert_eq!(chat_account_data(&full, 1).len(), CHAT_MESSAGES_ACCOUNT_LEN);
+
+        // an account sized for the dummy slots only has no room for inline text
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: get_init_chat_messages().try_to_vec().unwrap(), owner, executable: false };
+        let account = test_account.info();
+        assert_eq!(
+            process_instruction(&program_id, &[account], &add_message(1, inline_message("gm", "0001621449453837"))),
+            Err(ChatError::AccountTooSmall.into())
+        );
+    }
+
+    #[test]
+    fn test_message_buffer() {
+        let mut buffer = MessageBuffer::<CHAT_MESSAGE_SLOTS>::new();
+        assert_eq!(buffer.try_to_vec().unwrap(), get_init_chat_messages().try_to_vec().unwrap());
+        assert_eq!(buffer.free_slot_indices().len(), CHAT_MESSAGE_SLOTS);
+
+        assert_eq!(buffer.add(chat_message("first", DUMMY_CREATED_ON)), Ok(0));
+        assert_eq!(buffer.add(inline_message("gm", DUMMY_CREATED_ON)), Ok(1));
+        let data = buffer.try_to_vec().unwrap();
+        // readable as the account's Vec layout and back
+        let messages = unpack_chat_messages(&data).unwrap();
+        assert_eq!(messages[0].archive_id, "first");
+        assert_eq!(messages[1].inline_text.as_deref(), Some("gm"));
+        let decoded = MessageBuffer::<CHAT_MESSAGE_SLOTS>::try_from_slice(&data).unwrap();
+        assert_eq!(decoded.try_to_vec().unwrap(), data);
+        assert_eq!(decoded.count_active_messages(), 2);
+
+        // decoding checks the slot count against the type's
+        assert!(MessageBuffer::<21>::try_from_slice(&data).is_err());
+        assert!(MessageBuffer::<19>::try_from_slice(&data).is_err());
+        let mut longer = get_init_chat_messages();
+        longer.push(get_init_chat_message());
+        assert!(MessageBuffer::<CHAT_MESSAGE_SLOTS>::try_from_slice(&longer.try_to_vec().unwrap()).is_err());
+        assert!(MessageBuffer::<21>::try_from_slice(&longer.try_to_vec().unwrap()).is_ok());
+
+        for index in 2..CHAT_MESSAGE_SLOTS {
+            assert_eq!(buffer.add(chat_message("abc", DUMMY_CREATED_ON)), Ok(index));
+        }
+        // overflowing the slots is only caught when adding
+        assert_eq!(buffer.add(chat_message("overflow", DUMMY_CREATED_ON)), Err(ChatError::BufferFull.into()));
+
+        assert_eq!(buffer.reset(7), Ok(()));
+        assert_eq!(buffer.free_slot_indices(), vec![7]);
+        assert_eq!(buffer.add(chat_message("again", DUMMY_CREATED_ON)), Ok(7));
+        assert_eq!(buffer.reset(CHAT_MESSAGE_SLOTS), Err(ProgramError::InvalidArgument));
+        assert!(MessageBuffer::<0>::new().add(chat_message("none", DUMMY_CREATED_ON)).is_err());
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut messages = get_init_chat_messages();
+        for (index, archive_id) in [(1, "first"), (4, "second"), (5, "third"), (19, "fourth")].iter() {
+            messages[*index] = chat_message(archive_id, DUMMY_CREATED_ON);
+        }
+        compact(&mut messages);
+        assert_eq!(messages.len(), CHAT_MESSAGE_SLOTS);
+        let archive_ids: Vec<&str> = messages[..4].iter().map(|m| m.archive_id.as_str()).collect();
+        assert_eq!(archive_ids, ["first", "second", "third", "fourth"]);
+        assert_eq!(free_slot_indices(&messages), (4..CHAT_MESSAGE_SLOTS).collect::<Vec<_>>());
+        assert_eq!(messages[4].archive_id, DUMMY_TX_ID);
+
+        // through the instruction, signed by the chat account
+        let program_id = Pubkey::default();
+        let key = Pubkey::new_unique();
+        let sender = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let mut gapped = get_init_chat_messages();
+        gapped[2] = chat_message("first", DUMMY_CREATED_ON);
+        gapped[7] = chat_message("second", DUMMY_CREATED_ON);
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: gapped.try_to_vec().unwrap(), owner, executable: false };
+        let account = test_account.info();
+        let mut test_sender_account = TestAccount { key: sender, is_signer: true, is_writable: false, lamports: 0, data: Vec::new(), owner, executable: false };
+        let sender_account = test_sender_account.info();
+        let instruction_data = ChatInstruction::Compact.try_to_vec().unwrap();
+
+        assert_eq!(
+            process_instruction(&program_id, &[account.clone(), sender_account], &instruction_data),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(free_slot_indices(&unpack_chat_messages(&account.data.borrow()).unwrap())[..3], [0, 1, 3]);
+
+        process_instruction(&program_id, std::slice::from_ref(&account), &instruction_data).unwrap();
+        let stored = unpack_chat_messages(&account.data.borrow()).unwrap();
+        assert_eq!(stored[0].archive_id, "first");
+        assert_eq!(stored[1].archive_id, "second");
+        assert_eq!(free_slot_indices(&stored), (2..CHAT_MESSAGE_SLOTS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_buffer_capacity_from_bytes() {
+        for capacity in [1, CHAT_MESSAGE_SLOTS, 100].iter() {
+            let mut messages = Vec::new();
+            write_init_messages_into(&mut messages, *capacity);
+            let mut data = messages.try_to_vec().unwrap();
+            assert_eq!(buffer_capacity_from_bytes(&data), Some(*capacity));
+            // only the prefix is read
+            data.truncate(4);
+            assert_eq!(buffer_capacity_from_bytes(&data), Some(*capacity));
+        }
+        assert_eq!(buffer_capacity_from_bytes(&[0; CHAT_MESSAGES_ACCOUNT_LEN]), Some(0));
+        assert_eq!(buffer_capacity_from_bytes(&[20, 0, 0]), None);
+        assert_eq!(buffer_capacity_from_bytes(&[]), None);
+    }
+
+    #[test]
+    fn test_slot_byte_range() {
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let mut messages = get_init_chat_messages();
+        let mut data = messages.try_to_vec().unwrap();
+        assert_eq!(data.len(), 4 + CHAT_MESSAGE_SLOTS * FIXED_CHAT_MESSAGE_LEN);
+
+        for index in [0, 7, CHAT_MESSAGE_SLOTS - 1].iter() {
+            let message = chat_message(archive_id, "0001621449453837");
+            let slot = serialize_slot(&message).unwrap();
+            assert_eq!(slot.len(), FIXED_CHAT_MESSAGE_LEN);
+            data[slot_byte_range(*index, CHAT_MESSAGE_SLOTS).unwrap()].copy_from_slice(&slot);
+            messages[*index] = message;
+            assert_eq!(data, messages.try_to_vec().unwrap());
+        }
+        assert_eq!(unpack_chat_messages(&data).unwrap()[7].archive_id, archive_id);
+
+        assert_eq!(slot_byte_range(0, 1), Some(4..4 + FIXED_CHAT_MESSAGE_LEN));
+        assert_eq!(slot_byte_range(CHAT_MESSAGE_SLOTS, CHAT_MESSAGE_SLOTS), None);
+        assert_eq!(slot_byte_range(0, 0), None);
+        // shorter ids and inline text change the slot length
+        let mismatch = Err(ChatError::SlotLengthMismatch.into());
+        assert_eq!(serialize_slot(&chat_message("abc", DUMMY_CREATED_ON)), mismatch);
+        assert_eq!(serialize_slot(&inline_message("gm", DUMMY_CREATED_ON)), mismatch);
+    }
+
+    #[test]
+    fn test_write_slots_in_place() {
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let padding = [0xab; 16];
+        let mut messages = get_init_chat_messages();
+        let mut data = chat_account_data(&messages, 0);
+        data.extend_from_slice(&padding);
+        let mut test_account = make_account(data, &Pubkey::default());
+        let account = test_account.info();
+        let expected = |messages: &[ChatMessage], nonce: u64| {
+            let mut data = chat_account_data(messages, nonce);
+            data.extend_from_slice(&padding[data.len() - (4 + CHAT_MESSAGE_SLOTS * FIXED_CHAT_MESSAGE_LEN + 8)..]);
+            data
+        };
+
+        // fixed length slots are overwritten in place, leaving the bytes past the nonce alone
+        let key = *account.key;
+        let message = || ChatMessage { sender: key, ..chat_message(archive_id, "0001621449453837") };
+        assert_eq!(write_messages(&account, &account, None, 1, vec![message()]), Ok(1));
+        messages[0] = message();
+        assert_eq!(account.data.borrow().to_vec(), expected(&messages, 1));
+        assert_eq!(write_message_at(&account, &account, None, 2, 7, message()), Ok(()));
+        messages[7] = message();
+        assert_eq!(account.data.borrow().to_vec(), expected(&messages, 2));
+
+        // inline text shifts the slots after it, so the account is saved whole from then on
+        let inline = || ChatMessage { sender: key, ..inline_message("gm", "0001621449453838") };
+        assert_eq!(write_message_at(&account, &account, None, 3, 5, inline()), Ok(()));
+        messages[5] = inline();
+        assert_eq!(account.data.borrow().to_vec(), expected(&messages, 3));
+        assert_eq!(write_messages(&account, &account, None, 4, vec![message()]), Ok(1));
+        messages[1] = message();
+        assert_eq!(account.data.borrow().to_vec(), expected(&messages, 4));
+    }
+
+    #[test]
+    fn test_free_slot_indices() {
+        let mut messages = get_init_chat_messages();
+        assert_eq!(free_slot_indices(&messages), (0..CHAT_MESSAGE_SLOTS).collect::<Vec<_>>());
+
+        for index in [0, 3, 4, 19].iter() {
+            messages[*index] = chat_message("abc", DUMMY_CREATED_ON);
+        }
+        let free = free_slot_indices(&messages);
+        assert_eq!(free.len(), CHAT_MESSAGE_SLOTS - 4);
+        assert_eq!(&free[..4], &[1, 2, 5, 6]);
+        assert_eq!(free.last(), Some(&18));
+        assert_eq!(free.len() + count_active_messages(&messages), messages.len());
+
+        for message in messages.iter_mut() {
+            *message = chat_message("abc", DUMMY_CREATED_ON);
+        }
+        assert!(free_slot_indices(&messages).is_empty());
+        assert!(free_slot_indices(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_count_active_messages() {
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let mut messages = get_init_chat_messages();
+        assert_eq!(count_active_messages(&messages), 0);
+
+        messages[0] = chat_message(archive_id, "0001621449453837");
+        messages[7] = chat_message(archive_id, "0001621449453838");
+        assert_eq!(count_active_messages(&messages), 2);
+
+        for message in messages.iter_mut() {
+            *message = chat_message(archive_id, "0001621449453837");
+        }
+        assert_eq!(count_active_messages(&messages), messages.len());
+    }
+
+    #[test]
+    fn test_filter_messages_by_time() {
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let mut messages = get_init_chat_messages();
+        messages[0] = chat_message(archive_id, "0001621449450000");
+        messages[2] = chat_message(archive_id, "0001621449451000");
+        messages[3] = chat_message(archive_id, "0001621449452000");
+        messages[5] = chat_message(archive_id, "not a timestamp");
+
+        let found = filter_messages_by_time(&messages, 1621449450000, 1621449451000);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].created_on, "0001621449450000");
+        assert_eq!(found[1].created_on, "0001621449451000");
+
+        assert_eq!(filter_messages_by_time(&messages, 1621449451001, 1621449451999).len(), 0);
+        // dummy entries have a created_on of 0 but are never returned
+        assert_eq!(filter_messages_by_time(&messages, 0, u64::MAX).len(), 3);
+    }
+
+    #[test]
+    fn test_grow_chat_account_limits() {
+        let rent = Rent::default();
+        let len = get_init_chat_messages().try_to_vec().unwrap().len();
+        assert_eq!(rent_top_up(&rent, 0, len), rent.minimum_balance(len));
+        assert_eq!(rent_top_up(&rent, rent.minimum_balance(len), len + 100), rent.minimum_balance(len + 100) - rent.minimum_balance(len));
+        assert_eq!(rent_top_up(&rent, u64::MAX, len), 0);
+
+        let program_id = Pubkey::default();
+        let key = Pubkey::new_unique();
+        let payer_key = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let system_program_id = system_program::id();
+        let mut test_account = TestAccount { key, is_signer: false, is_writable: true, lamports: 0, data: get_init_chat_messages().try_to_vec().unwrap(), owner, executable: false };
+        let account = test_account.info();
+        let mut test_payer = TestAccount { key: payer_key, is_signer: true, is_writable: true, lamports: 0, data: Vec::new(), owner, executable: false };
+        let payer = test_payer.info();
+        let mut test_system_program_account = TestAccount { key: system_program_id, is_signer: false, is_writable: false, lamports: 0, data: Vec::new(), owner, executable: true };
+        let system_program_account = test_system_program_account.info();
+        let inline = add_message(1, inline_message("gm", "0001621449453837"));
+
+        // no payer to fund the growth
+        assert_eq!(
+            process_instruction(&program_id, &[account.clone(), payer.clone()], &inline),
+            Err(ChatError::AccountTooSmall.into())
+        );
+        // a program posing as the system program
+        assert_eq!(
+            process_instruction(&program_id, &[account.clone(), payer.clone(), account.clone()], &inline),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        // more than one instruction may grow an account by, which valid messages never need
+        let huge_len = account.data_len() + MAX_PERMITTED_DATA_INCREASE + 1;
+        assert_eq!(
+            grow_chat_account(&account, &payer, Some(&system_program_account), huge_len),
+            Err(ChatError::AccountTooSmall.into())
+        );
+        assert!(CHAT_MESSAGES_ACCOUNT_LEN - get_init_chat_messages().try_to_vec().unwrap().len() <= MAX_PERMITTED_DATA_INCREASE);
+        assert_eq!(count_active_messages(&unpack_chat_messages(&account.data.borrow()).unwrap()), 0);
+    }
+
+    #[test]
+    fn test_grow_chat_account() {
+        install_syscall_stubs();
+        let rent = Rent::default();
+        let data = get_init_chat_messages().try_to_vec().unwrap();
+        let len = data.len();
+        let payer_lamports = 1_000_000_000;
+        let mut input = runtime_input(&[
+            TestAccount { is_signer: false, ..make_account(data, &ID) },
+            TestAccount { lamports: payer_lamports, ..make_account(Vec::new(), &system_program::id()) },
+            TestAccount { key: system_program::id(), is_signer: false, is_writable: false, executable: true, ..make_account(Vec::new(), &Pubkey::default()) },
+        ]);
+        let accounts = runtime_accounts(&mut input);
+        let (account, payer) = (&accounts[0], &accounts[1]);
+        let write = |nonce: u64, text: &str| process_instruction(&ID, &accounts, &add_message(nonce, inline_message(text, "0001621449453837")));
+
+        // the account only holds its empty slots, so the first write grows it, funded by the payer
+        assert_eq!(write(1, "gm"), Ok(()));
+        let grown = account.data_len();
+        assert!(grown > len);
+        assert_eq!(account.lamports(), rent.minimum_balance(grown));
+        assert_eq!(payer.lamports(), payer_lamports - (rent.minimum_balance(grown) - rent.minimum_balance(len)));
+
+        // and the next write lands in the grown account
+        assert_eq!(write(2, "gm again"), Ok(()));
+        assert!(account.data_len() > grown);
+        assert_eq!(account.lamports(), rent.minimum_balance(account.data_len()));
+        let (messages, nonce) = unpack_chat_account(&account.data.borrow()).unwrap();
+        assert_eq!(nonce, 2);
+        assert_eq!(count_active_messages(&messages), 2);
+        assert!(messages.iter().filter(|message| !is_dummy_slot(message)).all(|message| message.sender == *payer.key));
+    }
+
+    #[test]
+    fn test_chat_errors() {
+        assert_eq!(ProgramError::from(ChatError::BufferFull), ProgramError::Custom(0));
+        assert_eq!(ProgramError::from(ChatError::InvalidArchiveId), ProgramError::Custom(1));
+        assert_eq!(ProgramError::from(ChatError::InvalidTimestamp), ProgramError::Custom(2));
+        assert_eq!(ProgramError::from(ChatError::NotOwner), ProgramError::Custom(3));
+        assert_eq!(ProgramError::from(ChatError::AccountTooSmall), ProgramError::Custom(4));
+        assert_eq!(ProgramError::from(ChatError::InvalidNonce), ProgramError::Custom(5));
+        assert_eq!(ProgramError::from(ChatError::InvalidSlotIndex), ProgramError::Custom(6));
+        assert_eq!(ProgramError::from(ChatError::SlotLengthMismatch), ProgramError::Custom(7));
+        assert_eq!(ProgramError::from(ChatError::SlotTaken), ProgramError::Custom(8));
+
+        let program_id = Pubkey::default();
+        let key = Pubkey::default();
+        let owner = Pubkey::default();
+        let archive_id = "1seRanklLU_1VTGkEk7P0xAwMJfA7owA1JHW5KyZKlY";
+        let created_on = "0001621449453837";
+        let mut data = get_init_chat_messages().try_to_vec().unwrap();
+        data.resize(CHAT_MESSAGES_ACCOUNT_LEN, 0);
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data, owner, executable: false };
+        let account = test_account.info();
+        let process = |nonce: u64, message: ChatMessage| {
+            process_instruction(&program_id, std::slice::from_ref(&account), &add_message(nonce, message))
+        };
+
+        for invalid in [&format!("{}a", archive_id), "not an arweave tx", DUMMY_TX_ID, ""].iter() {
+            assert_eq!(process(1, chat_message(invalid, created_on)), Err(ChatError::InvalidArchiveId.into()));
+        }
+        for invalid in ["", "00016214494538370", "2021-05-19", "-001621449453837"].iter() {
+            assert_eq!(process(1, chat_message(archive_id, invalid)), Err(ChatError::InvalidTimestamp.into()));
+        }
+        assert_eq!(count_active_messages(&unpack_chat_messages(&account.data.borrow()).unwrap()), 0);
+
+        // inline text needs no arweave tx, and a short timestamp is fine
+        assert_eq!(process(1, inline_message("gm", "1621449453837")), Ok(()));
+        assert_eq!(process(2, chat_message(archive_id, created_on)), Ok(()));
+        for nonce in 3..=CHAT_MESSAGE_SLOTS as u64 {
+            process(nonce, chat_message(archive_id, created_on)).unwrap();
+        }
+        assert_eq!(
+            process(CHAT_MESSAGE_SLOTS as u64 + 1, chat_message(archive_id, created_on)),
+            Err(ChatError::BufferFull.into())
+        );
+    }
+
+    #[test]
+    fn test_write_init_messages_into() {
+        let mut messages = Vec::new();
+        write_init_messages_into(&mut messages, CHAT_MESSAGE_SLOTS);
+        assert_eq!(messages.capacity(), CHAT_MESSAGE_SLOTS);
+        assert_eq!(messages.try_to_vec().unwrap(), get_init_chat_messages().try_to_vec().unwrap());
+
+        // appends to what is already there
+        let mut messages = vec![chat_message("first", DUMMY_CREATED_ON)];
+        write_init_messages_into(&mut messages, 2);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].archive_id, "first");
+        assert!(messages[1..].iter().all(is_dummy_slot));
+
+        write_init_messages_into(&mut messages, 0);
+        assert_eq!(messages.len(), 3);
+    }
+
+    #[test]
+    fn test_apply_message() {
+        let mut messages = get_init_chat_messages();
+
+        // fills the slots in order
+        assert_eq!(apply_message(&mut messages, chat_message("first", DUMMY_CREATED_ON)), Ok(0));
+        assert_eq!(apply_message(&mut messages, chat_message("second", DUMMY_CREATED_ON)), Ok(1));
+        assert_eq!(messages[0].archive_id, "first");
+        assert_eq!(messages[1].archive_id, "second");
+        assert_eq!(count_active_messages(&messages), 2);
+
+        // a slot reset to dummy data is overwritten first
+        messages[0] = get_init_chat_message();
+        assert_eq!(apply_message(&mut messages, chat_message("third", DUMMY_CREATED_ON)), Ok(0));
+        assert_eq!(messages[0].archive_id, "third");
+        assert_eq!(messages[1].archive_id, "second");
+
+        // a full buffer is left untouched
+        for index in 2..CHAT_MESSAGE_SLOTS {
+            assert_eq!(apply_message(&mut messages, chat_message(&index.to_string(), DUMMY_CREATED_ON)), Ok(index));
+        }
+        let full = messages.try_to_vec().unwrap();
+        assert_eq!(
+            apply_message(&mut messages, chat_message("overflow", DUMMY_CREATED_ON)),
+            Err(ChatError::BufferFull.into())
+        );
+        assert_eq!(messages.try_to_vec().unwrap(), full);
+
+        assert_eq!(
+            apply_message(&mut get_init_chat_messages(), inline_message(&"a".repeat(MAX_INLINE_TEXT_LEN + 1), DUMMY_CREATED_ON)),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[test]
+    fn test_add_messages() {
+        let program_id = Pubkey::default();
+        let key = Pubkey::default();
+        let owner = Pubkey::default();
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let mut messages = get_init_chat_messages();
+        for message in messages.iter_mut().skip(3) {
+            *message = chat_message(archive_id, "0001621449450000");
+        }
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: chat_account_data(&messages, 0), owner, executable: false };
+        let account = test_account.info();
+
+        let batch = || -> Vec<ChatMessage> {
+            (0..5).map(|i| chat_message(archive_id, &format!("000162144945383{}", i))).collect()
+        };
+        // 3 free slots for 5 messages
+        assert_eq!(write_messages(&account, &account, None, 1, batch()), Ok(3));
+        let stored = unpack_chat_messages(&account.data.borrow()).unwrap();
+        assert_eq!(count_active_messages(&stored), CHAT_MESSAGE_SLOTS);
+        assert_eq!(stored[0].created_on, "0001621449453830");
+        assert_eq!(stored[2].created_on, "0001621449453832");
+        assert_eq!(stored[3].created_on, "0001621449450000");
+
+        // nothing left to write into
+        let instruction_data = ChatInstruction::AddMessages { nonce: 2, messages: batch() }.try_to_vec().unwrap();
+        assert_eq!(
+            process_instruction(&program_id, std::slice::from_ref(&account), &instruction_data),
+            Err(ChatError::BufferFull.into())
+        );
+        assert_eq!(write_messages(&account, &account, None, 2, vec![]), Ok(0));
+
+        // the same batch through process_instruction on the original buffer
+        let instruction_data = ChatInstruction::AddMessages { nonce: 1, messages: batch() }.try_to_vec().unwrap();
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: chat_account_data(&messages, 0), owner, executable: false };
+        let account = test_account.info();
+        process_instruction(&program_id, std::slice::from_ref(&account), &instruction_data).unwrap();
+        let stored = unpack_chat_messages(&account.data.borrow()).unwrap();
+        assert_eq!(count_active_messages(&stored), CHAT_MESSAGE_SLOTS);
+        assert_eq!(stored[1].created_on, "0001621449453831");
+    }
+
+    #[test]
+    fn test_sender() {
+        let program_id = Pubkey::default();
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let sender = Pubkey::new_unique();
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: chat_account_data(&get_init_chat_messages(), 0), owner, executable: false };
+        let account = test_account.info();
+        let mut test_sender_account = TestAccount { key: sender, is_signer: true, is_writable: false, lamports: 0, data: Vec::new(), owner, executable: false };
+        let sender_account = test_sender_account.info();
+
+        // the sender in the instruction data is ignored
+        let forged = ChatMessage { sender: Pubkey::new_unique(), ..chat_message(archive_id, "0001621449453837") };
+        process_instruction(&program_id, &[account.clone(), sender_account], &add_message(1, forged)).unwrap();
+        // without a sender account the chat account is the sender
+        let instruction_data = add_message(2, chat_message(archive_id, "0001621449453838"));
+        process_instruction(&program_id, std::slice::from_ref(&account), &instruction_data).unwrap();
+
+        let messages = unpack_chat_messages(&account.data.borrow()).unwrap();
+        assert_eq!(messages[0].sender, sender);
+        assert_eq!(messages[1].sender, key);
+
+        let from_sender = messages_from(&messages, &sender);
+        assert_eq!(from_sender.len(), 1);
+        assert_eq!(from_sender[0].created_on, "0001621449453837");
+        assert_eq!(messages_from(&messages, &key).len(), 1);
+        // dummy slots carry the default pubkey but aren't messages
+        assert!(messages_from(&messages, &Pubkey::default()).is_empty());
+    }
+
+    #[test]
+    fn test_signer_required() {
+        let program_id = Pubkey::default();
+        let key = Pubkey::new_unique();
+        let sender = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let instruction_data = add_message(1, chat_message(archive_id, "0001621449453837"));
+        let mut test_account = TestAccount { key, is_signer: false, is_writable: true, lamports: 0, data: chat_account_data(&get_init_chat_messages(), 0), owner, executable: false };
+        let account = test_account.info();
+        let mut test_sender_account = TestAccount { key: sender, is_signer: false, is_writable: false, lamports: 0, data: Vec::new(), owner, executable: false };
+        let mut sender_account = test_sender_account.info();
+
+        assert_eq!(
+            process_instruction(&program_id, std::slice::from_ref(&account), &instruction_data),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            process_instruction(&program_id, &[account.clone(), sender_account.clone()], &instruction_data),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(count_active_messages(&unpack_chat_messages(&account.data.borrow()).unwrap()), 0);
+
+        // a signing sender may write to a chat account that doesn't sign
+        sender_account.is_signer = true;
+        process_instruction(&program_id, &[account.clone(), sender_account], &instruction_data).unwrap();
+        let mut account = account;
+        account.is_signer = true;
+        let instruction_data = add_message(2, chat_message(archive_id, "0001621449453838"));
+        process_instruction(&program_id, std::slice::from_ref(&account), &instruction_data).unwrap();
+        assert_eq!(count_active_messages(&unpack_chat_messages(&account.data.borrow()).unwrap()), 2);
+    }
+
+    #[test]
+    fn test_sorted_by_time() {
+        let mut messages = get_init_chat_messages();
+        messages[0] = chat_message("cccccccccccccccccccccccccccccccccccccccccc3", "0001621449453000");
+        messages[1] = chat_message("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa1", "0001621449451000");
+        messages[4] = chat_message("dddddddddddddddddddddddddddddddddddddddddd4", "0001621449452000");
+        messages[5] = chat_message("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb2", "0001621449451000");
+        messages[9] = chat_message("eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee5", "not a timestamp");
+        messages[12] = chat_message("ffffffffffffffffffffffffffffffffffffffffff6", "0001621449450000");
+
+        let sorted: Vec<&str> = sorted_by_time(&messages).iter().map(|m| m.archive_id.as_str()).collect();
+        assert_eq!(
+            sorted,
+            vec![
+                "ffffffffffffffffffffffffffffffffffffffffff6",
+                // same timestamp, slot 1 before slot 5
+                "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa1",
+                "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb2",
+                "dddddddddddddddddddddddddddddddddddddddddd4",
+                "cccccccccccccccccccccccccccccccccccccccccc3",
+            ]
+        );
+        assert!(sorted_by_time(&get_init_chat_messages()).is_empty());
+    }
+
+    #[test]
+    fn test_process_instruction_never_panics() {
+        let program_id = Pubkey::default();
+        let key = Pubkey::default();
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let valid = add_message(1, chat_message(archive_id, "0001621449453837"));
+
+        let mut inputs: Vec<Vec<u8>> = vec![vec![], valid.clone()];
+        // truncated borsh
+        for len in 0..valid.len() {
+            inputs.push(valid[..len].to_vec());
+        }
+        // oversized: trailing bytes and strings too long for the account
+        let mut trailing = valid.clone();
+        trailing.extend_from_slice(&[0u8; 64]);
+        inputs.push(trailing);
+        inputs.push(add_message(1, chat_message(&"a".repeat(4096), "0001621449453837")));
+        inputs.push(vec![0xff; 1024]);
+        // arbitrary bytes
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for len in 0..512 {
+            let bytes = (0..len % 128)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    seed as u8
+                })
+                .collect();
+            inputs.push(bytes);
+        }
+
+        for instruction_data in inputs.iter() {
+            let owner = Pubkey::default();
+            let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: get_init_chat_messages().try_to_vec().unwrap(), owner, executable: false };
+            let account = test_account.info();
+            let _ = process_instruction(&program_id, &[account], instruction_data);
+        }
+
+        // a full buffer, a buffer of garbage and an account owned by someone else
+        let full: Vec<ChatMessage> = (0..20).map(|_| chat_message(archive_id, "0001621449453837")).collect();
+        let owner = Pubkey::default();
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: full.try_to_vec().unwrap(), owner, executable: false };
+        let account = test_account.info();
+        assert_eq!(
+            process_instruction(&program_id, &[account], &valid),
+            Err(ChatError::BufferFull.into())
+        );
+
+        // one message whose archive_id claims to be 4GB long
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: vec![1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff], owner, executable: false };
+        let account = test_account.info();
+        assert!(process_instruction(&program_id, &[account], &valid).is_err());
+
+        let other_owner = Pubkey::new_unique();
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: get_init_chat_messages().try_to_vec().unwrap(), owner: other_owner, executable: false };
+        let account = test_account.info();
+        assert_eq!(
+            process_instruction(&program_id, &[account], &valid),
+            Err(ChatError::NotOwner.into())
+        );
+
+        assert_eq!(
+            process_instruction(&program_id, &[], &valid),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn test_nonce() {
+        assert_eq!(check_nonce(0, 1), Ok(()));
+        assert_eq!(check_nonce(41, 42), Ok(()));
+        assert_eq!(check_nonce(0, 0), Err(ChatError::InvalidNonce.into()));
+        assert_eq!(check_nonce(u64::MAX, 0), Err(ChatError::InvalidNonce.into()));
+
+        let program_id = Pubkey::default();
+        let key = Pubkey::default();
+        let owner = Pubkey::default();
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: vec![0; CHAT_MESSAGES_ACCOUNT_LEN], owner, executable: false };
+        let account = test_account.info();
+        let process = |nonce: u64, archive_id: &str| {
+            process_instruction(&program_id, std::slice::from_ref(&account), &add_message(nonce, chat_message(archive_id, DUMMY_CREATED_ON)))
+        };
+
+        // in order
+        for (nonce, archive_id) in [(1, "first"), (2, "second"), (3, "third")].iter() {
+            assert_eq!(process(*nonce, archive_id), Ok(()));
+        }
+        // replayed, old and skipped ahead
+        for nonce in [3, 1, 0, 5, u64::MAX].iter() {
+            assert_eq!(process(*nonce, "replayed"), Err(ChatError::InvalidNonce.into()));
+        }
+        let (messages, nonce) = unpack_chat_account(&account.data.borrow()).unwrap();
+        assert_eq!(nonce, 3);
+        assert_eq!(count_active_messages(&messages), 3);
+        assert!(messages.iter().all(|m| m.archive_id != "replayed"));
+
+        // a batch takes one nonce, and compacting keeps it
+        let batch = ChatInstruction::AddMessages { nonce: 4, messages: vec![chat_message("fourth", DUMMY_CREATED_ON)] };
+        process_instruction(&program_id, std::slice::from_ref(&account), &batch.try_to_vec().unwrap()).unwrap();
+        assert_eq!(process(4, "replayed"), Err(ChatError::InvalidNonce.into()));
+        let compact = ChatInstruction::Compact.try_to_vec().unwrap();
+        process_instruction(&program_id, std::slice::from_ref(&account), &compact).unwrap();
+        assert_eq!(unpack_chat_account(&account.data.borrow()).unwrap().1, 4);
+        assert_eq!(process(5, "fifth"), Ok(()));
+
+        // accounts written before the nonce, without room for it, start at 0
+        let data = get_init_chat_messages().try_to_vec().unwrap();
+        assert_eq!(unpack_chat_account(&data).unwrap().1, 0);
+    }
+
+    #[test]
+    fn test_free_slot_count() {
+        let program_id = Pubkey::default();
+        let mut test_account = make_account(vec![0; CHAT_MESSAGES_ACCOUNT_LEN], &program_id);
+        let account = test_account.info();
+        assert_eq!(free_slot_count(&account), Ok(CHAT_MESSAGE_SLOTS));
+
+        let free_slots = ChatInstruction::FreeSlots.try_to_vec().unwrap();
+        for n in 1..=5 {
+            let message = chat_message(&format!("message{}", n), "0001621449453837");
+            process_instruction(&program_id, std::slice::from_ref(&account), &add_message(n, message)).unwrap();
+            assert_eq!(free_slot_count(&account), Ok(CHAT_MESSAGE_SLOTS - n as usize));
+
+            // reporting leaves the account as it is
+            let before = account.data.borrow().to_vec();
+            assert_eq!(process_instruction(&program_id, std::slice::from_ref(&account), &free_slots), Ok(()));
+            assert_eq!(account.data.borrow().to_vec(), before);
+        }
+
+        let messages: Vec<ChatMessage> = (0..CHAT_MESSAGE_SLOTS).map(|_| chat_message("full", DUMMY_CREATED_ON)).collect();
+        let mut test_full = make_account(chat_account_data(&messages, 0), &program_id);
+        let full = test_full.info();
+        assert_eq!(free_slot_count(&full), Ok(0));
+    }
+
+    #[test]
+    fn test_zeroed_account() {
+        assert!(is_zeroed(&[0; CHAT_MESSAGES_ACCOUNT_LEN]));
+        assert!(!is_zeroed(&[]));
+        assert!(!is_zeroed(&chat_account_data(&get_init_chat_messages(), 0)));
+        let mut data = vec![0; 64];
+        data[63] = 1;
+        assert!(!is_zeroed(&data));
+
+        // a freshly allocated account starts out as the dummy slots
+        let program_id = Pubkey::default();
+        let mut test_account = make_account(vec![0; CHAT_MESSAGES_ACCOUNT_LEN], &program_id);
+        let account = test_account.info();
+        let (messages, nonce) = load_chat_account(&account).unwrap();
+        assert_eq!(messages.len(), CHAT_MESSAGE_SLOTS);
+        assert_eq!(count_active_messages(&messages), 0);
+        assert_eq!(nonce, 0);
+
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        process_instruction(&program_id, std::slice::from_ref(&account), &add_message(1, chat_message(archive_id, "0001621449453837"))).unwrap();
+        let (messages, nonce) = unpack_chat_account(&account.data.borrow()).unwrap();
+        assert_eq!(messages.len(), CHAT_MESSAGE_SLOTS);
+        assert_eq!(messages[0].archive_id, archive_id);
+        assert_eq!(free_slot_indices(&messages).len(), CHAT_MESSAGE_SLOTS - 1);
+        assert_eq!(nonce, 1);
+    }
+
+    #[test]
+    fn test_read_chat_messages() {
+        let mut messages = get_init_chat_messages();
+        messages[3] = inline_message("gm", "0001621449453837");
+        let serialized = messages.try_to_vec().unwrap();
+        let mut data = serialized.clone();
+        data.extend_from_slice(&[0; 512]);
+
+        let mut rest = &data[..];
+        let decoded = read_chat_messages(&mut rest).unwrap();
+        assert_eq!(decoded.try_to_vec().unwrap(), serialized);
+        assert_eq!(rest.len(), 512);
+        // the padding reads as a nonce of 0
+        assert_eq!(unpack_chat_account(&data).unwrap().1, 0);
+
+        // a prefix counting more slots than the data holds is rejected before decoding
+        let mut data = u32::MAX.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0; 512]);
+        assert_eq!(read_chat_messages(&mut &data[..]).unwrap_err().kind(), InvalidData);
+        assert!(read_chat_messages(&mut &[0u8; 3][..]).is_err());
+        assert_eq!(read_chat_messages(&mut &[0u8; 4][..]).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_write_at() {
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let mut messages = get_init_chat_messages();
+        assert_eq!(write_at(&mut messages, 5, chat_message("first", DUMMY_CREATED_ON)), Ok(()));
+        assert_eq!(free_slot_indices(&messages)[..6], [0, 1, 2, 3, 4, 6]);
+        // the sender's own message is overwritten
+        assert_eq!(write_at(&mut messages, 5, chat_message("edited", DUMMY_CREATED_ON)), Ok(()));
+        assert_eq!(messages[5].archive_id, "edited");
+        // anyone else's is not
+        let other = ChatMessage { sender: Pubkey::new_unique(), ..chat_message("other", DUMMY_CREATED_ON) };
+        assert_eq!(write_at(&mut messages, 5, other), Err(ChatError::SlotTaken.into()));
+        assert_eq!(messages[5].archive_id, "edited");
+        assert_eq!(
+            write_at(&mut messages, CHAT_MESSAGE_SLOTS, chat_message("past", DUMMY_CREATED_ON)),
+            Err(ChatError::InvalidSlotIndex.into())
+        );
+        assert_eq!(
+            write_at(&mut messages, 0, chat_message(DUMMY_TX_ID, DUMMY_CREATED_ON)),
+            Err(ChatError::InvalidArchiveId.into())
+        );
+        assert_eq!(count_active_messages(&messages), 1);
+
+        // through the instruction
+        let program_id = Pubkey::default();
+        let mut test_account = make_account(chat_account_data(&get_init_chat_messages(), 0), &program_id);
+        let account = test_account.info();
+        let write = |nonce: u64, index: u16| {
+            let instruction = ChatInstruction::WriteAt { nonce, index, message: chat_message(archive_id, "0001621449453837") };
+            process_instruction(&program_id, std::slice::from_ref(&account), &instruction.try_to_vec().unwrap())
+        };
+        assert_eq!(write(1, 19), Ok(()));
+        assert_eq!(write(2, CHAT_MESSAGE_SLOTS as u16), Err(ChatError::InvalidSlotIndex.into()));
+        assert_eq!(write(2, u16::MAX), Err(ChatError::InvalidSlotIndex.into()));
+        let (stored, nonce) = unpack_chat_account(&account.data.borrow()).unwrap();
+        assert_eq!(nonce, 1);
+        assert_eq!(free_slot_indices(&stored), (0..19).collect::<Vec<_>>());
+        assert_eq!(stored[19].sender, *account.key);
+        assert_eq!(write(1, 0), Err(ChatError::InvalidNonce.into()));
+
+        // a second signer can't overwrite the chat account's message
+        let mut test_sender = TestAccount { key: Pubkey::new_unique(), is_signer: true, is_writable: true, lamports: 0, data: vec![], owner: program_id, executable: false };
+        let sender = test_sender.info();
+        let write_as_sender = |nonce: u64, index: u16| {
+            let instruction = ChatInstruction::WriteAt { nonce, index, message: chat_message(archive_id, "0001621449453838") };
+            process_instruction(&program_id, &[account.clone(), sender.clone()], &instruction.try_to_vec().unwrap())
+        };
+        assert_eq!(write_as_sender(2, 19), Err(ChatError::SlotTaken.into()));
+        let (stored, nonce) = unpack_chat_account(&account.data.borrow()).unwrap();
+        assert_eq!((nonce, stored[19].sender, stored[19].created_on.as_str()), (1, *account.key, "0001621449453837"));
+        // but may take a free slot, and then edit it
+        assert_eq!(write_as_sender(2, 3), Ok(()));
+        assert_eq!(write_as_sender(3, 3), Ok(()));
+        assert_eq!(unpack_chat_account(&account.data.borrow()).unwrap().0[3].sender, *sender.key);
+        assert_eq!(write(4, 3), Err(ChatError::SlotTaken.into()));
+    }
+
+    #[test]
+    fn test_empty_instruction_data() {
+        let program_id = Pubkey::default();
+        let key = Pubkey::default();
+        let owner = Pubkey::default();
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: get_init_chat_messages().try_to_vec().unwrap(), owner, executable: false };
+        let account = test_account.info();
+        assert_eq!(
+            process_instruction(&program_id, std::slice::from_ref(&account), &[]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(count_active_messages(&unpack_chat_messages(&account.data.borrow()).unwrap()), 0);
+        // rejected before the accounts are read
+        assert_eq!(process_instruction(&program_id, &[], &[]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(dispatch_instruction(&program_id, &[account], &[CHAT_INSTRUCTION]), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_price_feed_account_versions() {
+        let answer: u128 = 41_234_567_890;
+
+        // v1: version and answer only
+        let mut v1 = vec![1];
+        v1.extend_from_slice(&answer.to_le_bytes());
+        let account = PriceFeedAccount::deserialize_versioned(&v1).unwrap();
+        assert_eq!(
+            account,
+            PriceFeedAccount { version: PRICE_FEED_ACCOUNT_VERSION, answer, decimals: PRICE_DECIMALS, ..PriceFeedAccount::default() }
+        );
+
+        // v2: no cumulative price yet
+        let mut v2 = vec![2];
+        v2.extend_from_slice(&answer.to_le_bytes());
+        v2.extend_from_slice(&1_621_449_453i64.to_le_bytes());
+        v2.push(8);
+        assert_eq!(
+            PriceFeedAccount::deserialize_versioned(&v2).unwrap(),
+            PriceFeedAccount { version: PRICE_FEED_ACCOUNT_VERSION, answer, timestamp: 1_621_449_453, decimals: 8, cumulative_price: 0 }
+        );
+        assert!(PriceFeedAccount::deserialize_versioned(&v2[..20]).is_err());
+
+        // v3, in an account with room to spare
+        let v3 = PriceFeedAccount { version: 3, answer, timestamp: 1_621_449_453, decimals: 8, cumulative_price: 7 };
+        let mut data = v3.try_to_vec().unwrap();
+        assert_eq!(data.len(), 1 + 16 + 8 + 1 + 16);
+        data.extend_from_slice(&[0; 32]);
+        assert_eq!(PriceFeedAccount::deserialize_versioned(&data).unwrap(), v3);
+
+        // upgrading a v1 account rewrites it in the current layout
+        let mut upgraded = PriceFeedAccount::deserialize_versioned(&v1).unwrap();
+        upgraded.timestamp = 1_621_449_453;
+        let mut data = vec![0; 42];
+        upgraded.serialize(&mut &mut data[..]).unwrap();
+        assert_eq!(PriceFeedAccount::deserialize_versioned(&data).unwrap(), upgraded);
+
+        assert_eq!(
+            PriceFeedAccount::deserialize_versioned(&[0; 42]).unwrap(),
+            PriceFeedAccount { version: PRICE_FEED_ACCOUNT_VERSION, ..PriceFeedAccount::default() }
+        );
+        assert_eq!(PriceFeedAccount::deserialize_versioned(&[4; 42]), Err(ProgramError::InvalidAccountData));
+        assert_eq!(PriceFeedAccount::deserialize_versioned(&[]), Err(ProgramError::InvalidAccountData));
+        assert!(PriceFeedAccount::deserialize_versioned(&v1[..8]).is_err());
+        assert!(PriceFeedAccount::deserialize_versioned(&v3.try_to_vec().unwrap()[..30]).is_err());
+    }
+
+    #[test]
+    fn test_twap() {
+        let mut account = PriceFeedAccount::default();
+
+        // nothing accrues before the first update
+        account.accumulate(1_621_449_400);
+        account.answer = 40_000_000_000;
+        assert_eq!(account.cumulative_price, 0);
+        let start = PriceFeedAccount { ..account };
+
+        // 40 for 60 seconds, then 46 for 30 seconds
+        account.accumulate(1_621_449_460);
+        account.answer = 46_000_000_000;
+        assert_eq!(account.cumulative_price - start.cumulative_price, 40_000_000_000 * 60);
+        assert_eq!(twap(&start, &account), Some(40_000_000_000));
+        account.accumulate(1_621_449_490);
+        assert_eq!(account.cumulative_price - start.cumulative_price, 40_000_000_000 * 60 + 46_000_000_000 * 30);
+        assert_eq!(twap(&start, &account), Some(42_000_000_000));
+
+        // a clock going backwards accrues nothing
+        let cumulative_price = account.cumulative_price;
+        account.accumulate(1_621_449_480);
+        assert_eq!(account.cumulative_price, cumulative_price);
+
+        // the accumulator wraps and deltas stay right
+        let start = PriceFeedAccount { answer: 10, timestamp: 100, cumulative_price: u128::MAX - 5, ..PriceFeedAccount::default() };
+        let mut end = PriceFeedAccount { ..start };
+        end.accumulate(110);
+        assert_eq!(end.cumulative_price, 94);
+        assert_eq!(twap(&start, &end), Some(10));
+
+        assert_eq!(twap(&end, &start), None);
+        assert_eq!(twap(&end, &end), None);
+    }
+
+    #[test]
+    fn test_price_deviation() {
+        // 5% bound
+        let max_deviation_bps = 500;
+        let previous = Some(40_000_000_000);
+        assert!(check_price_deviation(None, Some(41_000_000_000), max_deviation_bps).is_ok());
+        assert!(check_price_deviation(None, None, max_deviation_bps).is_ok());
+        assert!(check_price_deviation(previous, Some(42_000_000_000), max_deviation_bps).is_ok());
+        assert!(check_price_deviation(previous, Some(38_000_000_000), max_deviation_bps).is_ok());
+        assert_eq!(
+            check_price_deviation(previous, Some(42_000_000_001), max_deviation_bps),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(check_price_deviation(previous, Some(0), max_deviation_bps), Err(ProgramError::InvalidArgument));
+        // losing the price is never within bounds, however wide
+        assert_eq!(check_price_deviation(previous, None, u64::MAX), Err(ProgramError::InvalidArgument));
+        assert!(check_price_deviation(previous, Some(40_000_000_000), 0).is_ok());
+
+        // nothing stored yet, or no current price when the last update ran
+        assert_eq!(PriceFeedAccount::default().previous_answer(), None);
+        assert_eq!(PriceFeedAccount { answer: 40_000_000_000, ..PriceFeedAccount::default() }.previous_answer(), previous);
+
+        assert_eq!(check_no_price_instruction_data(&[]), Ok(()));
+        assert_eq!(check_no_price_instruction_data(&500u64.to_le_bytes()), Err(ProgramError::InvalidInstructionData));
+
+        // a spike is rejected and the stored price kept
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let stored = PriceFeedAccount { version: PRICE_FEED_ACCOUNT_VERSION, answer: 40_000_000_000, timestamp: 1_621_449_453, decimals: 9, cumulative_price: 0 };
+        let mut test_account = TestAccount { key, is_signer: false, is_writable: true, lamports: 0, data: stored.try_to_vec().unwrap(), owner, executable: false };
+        let account = test_account.info();
+        assert_eq!(
+            store_price(&account, Some(80_000_000_000), 9, Some(max_deviation_bps)),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(PriceFeedAccount::deserialize_versioned(&account.data.borrow()).unwrap(), stored);
+    }
+
+    #[test]
+    fn test_price_deviation_config() {
+        let (config_key, _) = Pubkey::find_program_address(&[CONFIG_SEED], &ID);
+        let config = Config { authority: Pubkey::new_unique(), max_deposit: 0, paused: false, max_price_deviation_bps: Some(500) };
+        let mut stored = TestAccount { key: config_key, ..make_account(anchor_account_data(&config), &ID) };
+        assert_eq!(load_max_price_deviation(&ID, &stored.info()), Ok(Some(500)));
+
+        let unbounded = Config { max_price_deviation_bps: None, ..config };
+        let mut stored = TestAccount { key: config_key, ..make_account(anchor_account_data(&unbounded), &ID) };
+        assert_eq!(load_max_price_deviation(&ID, &stored.info()), Ok(None));
+
+        // a config of the caller's own can't lift the bound
+        let mut foreign = make_account(anchor_account_data(&unbounded), &ID);
+        assert_eq!(load_max_price_deviation(&ID, &foreign.info()), Err(ProgramError::InvalidArgument));
+        let mut not_owned = TestAccount { key: config_key, ..make_account(anchor_account_data(&unbounded), &Pubkey::new_unique()) };
+        assert_eq!(load_max_price_deviation(&ID, &not_owned.info()), Err(ProgramError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_get_price_from_feed() {
+        install_syscall_stubs();
+        let stored_price = |accounts: &[TestAccount]| PriceFeedAccount::deserialize_versioned(&accounts[0].data).unwrap();
+        let get = |accounts: &mut Vec<TestAccount>| {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            get_price(&ID, &infos, &[])
+        };
+
+        let mut accounts = price_accounts(&PriceFeedAccount::default(), None, vec![feed_account(7, 9, 41_000_000_000)]);
+        assert_eq!(get(&mut accounts), Ok(()));
+        let stored = stored_price(&accounts);
+        assert_eq!((stored.answer, stored.timestamp), (41_000_000_000, NOW));
+
+        // the feed is only read through the chainlink store
+        let mut accounts = price_accounts(&PriceFeedAccount::default(), None, vec![feed_account(7, 9, 41_000_000_000)]);
+        accounts[2] = TestAccount { key: Pubkey::new_unique(), ..store_program() };
+        assert_eq!(get(&mut accounts), Err(ProgramError::IncorrectProgramId));
+        assert_eq!(accounts[0].data, PriceFeedAccount::default().try_to_vec().unwrap());
+
+        // neither a feed without rounds nor a negative answer is a price
+        assert_eq!(chainlink::get_price(&store_program().info(), &feed_account(0, 9, 41_000_000_000).info()), Ok(None));
+        assert_eq!(chainlink::get_price(&store_program().info(), &feed_account(7, 9, -1).info()), Ok(None));
+    }
+
+    #[test]
+    fn test_get_price_feed_decimals() {
+        install_syscall_stubs();
+        let answer = 4_123_456_789_000;
+        let stored_with = |decimals: u8| {
+            let mut accounts = price_accounts(&PriceFeedAccount::default(), None, vec![feed_account(7, decimals, answer)]);
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            get_price(&ID, &infos, &[]).unwrap();
+            drop(infos);
+            PriceFeedAccount::deserialize_versioned(&accounts[0].data).unwrap()
+        };
+
+        // the decimals the store reports for the feed, not `PRICE_DECIMALS`
+        let eight = stored_with(8);
+        let nine = stored_with(9);
+        assert_eq!((eight.answer, eight.decimals), (answer as u128, 8));
+        assert_eq!((nine.answer, nine.decimals), (answer as u128, 9));
+        assert_eq!(price_log(Some(eight.answer), eight.decimals), "Price is 41234.56789000");
+        assert_eq!(price_log(Some(nine.answer), nine.decimals), "Price is 4123.456789000");
+
+        let mut store = store_program();
+        assert_eq!(chainlink::get_decimals(&store.info(), &feed_account(7, 18, answer).info()), Ok(18));
+        store.key = Pubkey::new_unique();
+        assert_eq!(chainlink::get_decimals(&store.info(), &feed_account(7, 18, answer).info()), Err(ProgramError::IncorrectProgramId));
+    }
+
+    #[test]
+    fn test_resolve_decimals() {
+        assert_eq!(resolve_decimals(Some(8), Some(6)), 8);
+        assert_eq!(resolve_decimals(Some(8), None), 8);
+        assert_eq!(resolve_decimals(None, Some(6)), 6);
+        assert_eq!(resolve_decimals(None, None), u32::from(PRICE_DECIMALS));
+        // a feed reporting 0 decimals is still the feed's answer
+        assert_eq!(resolve_decimals(Some(0), Some(6)), 0);
+    }
+
+    #[test]
+    fn test_price_decimals() {
+        let stored = PriceFeedAccount { version: PRICE_FEED_ACCOUNT_VERSION, answer: 40_000_000_000, timestamp: 1_621_449_453, decimals: 9, cumulative_price: 0 };
+        assert_eq!(check_price_decimals(&stored, 9), Ok(()));
+        assert_eq!(check_price_decimals(&stored, 8), Err(PriceError::DecimalsMismatch.into()));
+        assert_eq!(ProgramError::from(PriceError::DecimalsMismatch), ProgramError::Custom(100));
+        assert_eq!(ProgramError::from(PriceError::NoCurrentPrice), ProgramError::Custom(101));
+        // no price error reads as a chat error
+        assert_ne!(ProgramError::from(PriceError::DecimalsMismatch), ProgramError::from(ChatError::BufferFull));
+        assert!(PRICE_ERROR_OFFSET > ChatError::SlotTaken as u32);
+        // nothing stored yet
+        assert_eq!(check_price_decimals(&PriceFeedAccount::default(), 8), Ok(()));
+        // v1 accounts were never timestamped, so their first update may set the decimals
+        let mut v1 = vec![1];
+        v1.extend_from_slice(&40_000_000_000u128.to_le_bytes());
+        assert_eq!(check_price_decimals(&PriceFeedAccount::deserialize_versioned(&v1).unwrap(), 18), Ok(()));
+
+        // a mismatched update leaves the stored price untouched
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let mut test_account = TestAccount { key, is_signer: false, is_writable: true, lamports: 0, data: stored.try_to_vec().unwrap(), owner, executable: false };
+        let account = test_account.info();
+        assert_eq!(store_price(&account, Some(4_000_000_000), 8, None), Err(PriceError::DecimalsMismatch.into()));
+        assert_eq!(PriceFeedAccount::deserialize_versioned(&account.data.borrow()).unwrap(), stored);
+    }
+
+    #[test]
+    fn test_price_log() {
+        let price = Some(4_123_456_789_000);
+        assert_eq!(price_log(price, 9), "Price is 4123.456789000");
+        assert_eq!(price_log(price, 8), "Price is 41234.56789000");
+        assert_eq!(price_log(Some(1), 8), "Price is 0.00000001");
+        assert_eq!(price_log(None, 8), "No current price");
+    }
+
+    #[test]
+    fn test_answer_to_decimal() {
+        assert_eq!(answer_to_decimal(1_500_000_000, 9).to_string(), "1.500000000");
+        assert_eq!(answer_to_decimal(1_500_000_000, 9), Decimal::new(1_500_000_000, 9));
+        assert_eq!(answer_to_decimal(4_123_456_789_000, 8).to_string(), "41234.56789000");
+        assert_eq!(answer_to_decimal(0, 9).to_string(), "0.000000000");
+    }
+
+    #[test]
+    fn test_signed_answer_to_decimal() {
+        let spread = signed_answer_to_decimal(-25_000_000, 8);
+        assert_eq!(spread.to_string(), "-0.25000000");
+        assert!(spread < signed_answer_to_decimal(25_000_000, 8));
+        assert_eq!(spread.to_unsigned(), None);
+        assert_eq!(signed_answer_to_decimal(4_123_456_789_000, 8).to_unsigned(), Some(answer_to_decimal(4_123_456_789_000, 8)));
+    }
+
+    #[test]
+    fn test_median_price() {
+        assert_eq!(median_price(&[]), None);
+        assert_eq!(median_price(&[7]), Some(7));
+        assert_eq!(median_price(&[30, 10, 20]), Some(20));
+        // one feed spiking doesn't move the median
+        assert_eq!(median_price(&[100, 101, u128::MAX, 99, 100]), Some(100));
+        // even counts take the lower middle price
+        assert_eq!(median_price(&[40, 10, 30, 20]), Some(20));
+        assert_eq!(median_price(&[2, 1]), Some(1));
+    }
+
+    #[test]
+    fn test_get_median_price() {
+        install_syscall_stubs();
+        let stored = PriceFeedAccount { version: PRICE_FEED_ACCOUNT_VERSION, answer: 40_000_000_000, timestamp: 1_621_449_453, decimals: 9, cumulative_price: 0 };
+        let get = |accounts: &mut Vec<TestAccount>| {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            get_median_price(&ID, &infos, &[])
+        };
+
+        let feeds = vec![feed_account(7, 9, 41_000_000_000), feed_account(3, 9, 90_000_000_000), feed_account(5, 9, 40_500_000_000), feed_account(0, 9, 0)];
+        let mut accounts = price_accounts(&stored, None, feeds);
+        assert_eq!(get(&mut accounts), Ok(()));
+        let updated = PriceFeedAccount::deserialize_versioned(&accounts[0].data).unwrap();
+        assert_eq!((updated.answer, updated.timestamp), (41_000_000_000, NOW));
+
+        // no feeds at all
+        let mut accounts = price_accounts(&stored, None, vec![]);
+        assert_eq!(get(&mut accounts), Err(PriceError::NoCurrentPrice.into()));
+        assert_eq!(accounts[0].data, stored.try_to_vec().unwrap());
+
+        // feeds, but none with a current price
+        let mut accounts = price_accounts(&stored, None, vec![feed_account(0, 9, 0), feed_account(7, 9, -25_000_000)]);
+        assert_eq!(get(&mut accounts), Err(PriceError::NoCurrentPrice.into()));
+        assert_eq!(accounts[0].data, stored.try_to_vec().unwrap());
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let program_id = Pubkey::default();
+        let key = Pubkey::default();
+        let owner = Pubkey::default();
+        let mut test_account = TestAccount { key, is_signer: true, is_writable: true, lamports: 0, data: get_init_chat_messages().try_to_vec().unwrap(), owner, executable: false };
+        let account = test_account.info();
+        let accounts = vec![account];
+        let add_message = add_message(1, chat_message("dispatched", DUMMY_CREATED_ON));
+        let with_discriminant = |discriminant: u8, data: &[u8]| [&[discriminant], data].concat();
+
+        // the chat handler stores the message
+        dispatch_instruction(&program_id, &accounts, &with_discriminant(CHAT_INSTRUCTION, &add_message)).unwrap();
+        let messages = unpack_chat_messages(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(messages[0].archive_id, "dispatched");
+
+        // the price handlers take no data
+        for discriminant in [GET_PRICE_INSTRUCTION, GET_MEDIAN_PRICE_INSTRUCTION].iter() {
+            assert_eq!(
+                dispatch_instruction(&program_id, &accounts, &with_discriminant(*discriminant, &add_message)),
+                Err(ProgramError::InvalidInstructionData)
+            );
+            assert_eq!(
+                dispatch_instruction(&program_id, &[], &with_discriminant(*discriminant, &[])),
+                Err(ProgramError::NotEnoughAccountKeys)
+            );
+        }
+        assert_eq!(count_active_messages(&unpack_chat_messages(&accounts[0].data.borrow()).unwrap()), 1);
+
+        assert_eq!(dispatch_instruction(&program_id, &accounts, &with_discriminant(3, &add_message)), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(dispatch_instruction(&program_id, &accounts, &[]), Err(ProgramError::InvalidInstructionData));
+
+        // anchor's entrypoint hands everything that isn't a dog_money instruction to the fallback
+        let mut test_account = make_account(get_init_chat_messages().try_to_vec().unwrap(), &ID);
+        let account = test_account.info();
+        let message = ChatInstruction::AddMessage { nonce: 1, message: chat_message("entry", DUMMY_CREATED_ON) };
+        entry(&ID, std::slice::from_ref(&account), &with_discriminant(CHAT_INSTRUCTION, &message.try_to_vec().unwrap())).unwrap();
+        assert_eq!(unpack_chat_messages(&account.data.borrow()).unwrap()[0].archive_id, "entry");
+        assert_eq!(entry(&ID, std::slice::from_ref(&account), &[]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(entry(&ID, &[account], &with_discriminant(3, &add_message)), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_sanity() {
+        let program_id = Pubkey::default();
+        let mut test_account = make_account(chat_account_data(&get_init_chat_messages(), 0), &program_id);
+        let account = test_account.info();
+        assert_eq!(account.lamports(), Rent::default().minimum_balance(account.data_len()));
+
+        let archive_id = "abcdefghijabcdefghijabcdefghijabcdefghijabc";
+        let created_on = "0001621449453837";
+        let instruction_data = add_message(1, chat_message(archive_id, created_on));
+
+        let accounts = vec![account];
+
+        process_instruction(&program_id, &accounts, &instruction_data).unwrap();
+        let chat_messages = &unpack_chat_messages(&accounts[0].data.borrow()).unwrap()[0];
+        // I added first data and expect it to contain the given data
+        assert_eq!(chat_messages.archive_id, archive_id);
+        assert_eq!(chat_messages.created_on, created_on);
+        assert_eq!(chat_messages.sender, *accounts[0].key);
     }
 }
@@ -128,6 +128,7 @@ pub fn swap<'a, 'b, 'c, 'info>(
     amount_in: u64,
     minimum_amount_out: u64,
 ) -> ProgramResult {
+    ctx.accounts.check_distinct_mints()?;
     let ix = swap_client::instruction::swap(
         ctx.accounts.user.token_program.key,
         ctx.accounts.user.swap.key,
@@ -161,6 +162,24 @@ pub fn swap<'a, 'b, 'c, 'info>(
     )
 }
 
+/// Creates and invokes a [swap_client::instruction::swap] instruction, rejecting it
+/// with [swap_client::error::SwapError::DeadlineExceeded] once the clock passes `deadline`.
+///
+/// # Arguments:
+///
+/// * `amount_in` - Amount of [`Swap::input`] tokens to swap.
+/// * `minimum_amount_out` - Minimum amount of [`Swap::output`] tokens to receive.
+/// * `deadline` - Unix timestamp after which the swap is rejected.
+pub fn swap_with_deadline<'a, 'b, 'c, 'info>(
+    ctx: CpiContext<'a, 'b, 'c, 'info, Swap<'info>>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    deadline: i64,
+) -> ProgramResult {
+    ctx.accounts.user.check_deadline(deadline)?;
+    swap(ctx, amount_in, minimum_amount_out)
+}
+
 /// Creates and invokes a [swap_client::instruction::withdraw_one] instruction.
 ///
 /// # Arguments:
@@ -396,6 +415,19 @@ pub struct Swap<'info> {
     pub output: SwapOutput<'info>,
 }
 
+impl<'info> Swap<'info> {
+    /// Rejects a swap whose input and output token accounts hold the same mint.
+    pub fn check_distinct_mints(&self) -> ProgramResult {
+        let input_mint = token_account_mint(&self.input.user)?;
+        let output_mint = token_account_mint(&self.output.user_token.user)?;
+        require!(
+            input_mint != output_mint,
+            swap_client::error::SwapError::RepeatedMint
+        );
+        Ok(())
+    }
+}
+
 /// Accounts for a 'withdraw_one' instruction.
 #[derive(Accounts)]
 pub struct WithdrawOne<'info> {
@@ -492,6 +524,16 @@ pub struct SwapUserContext<'info> {
     pub clock: AccountInfo<'info>,
 }
 
+impl<'info> SwapUserContext<'info> {
+    /// Rejects an instruction once [`SwapUserContext::clock`] has passed `deadline`,
+    /// see [swap_client::instruction::check_deadline].
+    pub fn check_deadline(&self, deadline: i64) -> ProgramResult {
+        let clock = Clock::from_account_info(&self.clock)?;
+        swap_client::instruction::check_deadline(clock.unix_timestamp, deadline)?;
+        Ok(())
+    }
+}
+
 /// Accounts for an instruction that requires admin permission.
 #[derive(Accounts, Clone)]
 pub struct AdminUserContext<'info> {
@@ -510,6 +552,12 @@ pub struct AdminUserContextWithClock<'info> {
     pub clock: AccountInfo<'info>,
 }
 
+/// Reads the mint of an SPL token account.
+fn token_account_mint(account: &AccountInfo) -> Result<Pubkey, ProgramError> {
+    let data = account.try_borrow_data()?;
+    anchor_spl::token::TokenAccount::try_deserialize(&mut &data[..]).map(|account| account.mint)
+}
+
 /// Swap information.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct SwapInfo(swap_client::state::SwapInfo);
@@ -574,3 +622,145 @@ impl anchor_lang::Id for StableSwap {
         ID
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Data of an initialized SPL token account of `mint`.
+    fn token_account_data(mint: &Pubkey) -> Vec<u8> {
+        let mut data = vec![0u8; 165];
+        data[..32].copy_from_slice(mint.as_ref());
+        // state: initialized
+        data[108] = 1;
+        data
+    }
+
+    fn account_info<'a>(
+        key: &'a Pubkey,
+        lamports: &'a mut u64,
+        data: &'a mut [u8],
+    ) -> AccountInfo<'a> {
+        AccountInfo::new(
+            key,
+            false,
+            true,
+            lamports,
+            data,
+            &anchor_spl::token::ID,
+            false,
+            0,
+        )
+    }
+
+    /// Swap accounts from `input` to `output`, with `unused` for every other account.
+    fn swap_accounts<'a>(
+        input: &AccountInfo<'a>,
+        output: &AccountInfo<'a>,
+        unused: &AccountInfo<'a>,
+    ) -> Swap<'a> {
+        Swap {
+            user: SwapUserContext {
+                token_program: unused.clone(),
+                swap_authority: unused.clone(),
+                user_authority: unused.clone(),
+                swap: unused.clone(),
+                clock: unused.clone(),
+            },
+            input: SwapToken {
+                user: input.clone(),
+                reserve: unused.clone(),
+            },
+            output: SwapOutput {
+                user_token: SwapToken {
+                    user: output.clone(),
+                    reserve: unused.clone(),
+                },
+                fees: unused.clone(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_check_distinct_mints() {
+        let mint_a = Pubkey::new_unique();
+        let mint_b = Pubkey::new_unique();
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let [mut l0, mut l1, mut l2, mut l3] = [0u64; 4];
+        let mut input_data = token_account_data(&mint_a);
+        let mut same_data = token_account_data(&mint_a);
+        let mut other_data = token_account_data(&mint_b);
+        let mut no_data = [];
+        let input = account_info(&keys[0], &mut l0, &mut input_data);
+        let same = account_info(&keys[1], &mut l1, &mut same_data);
+        let other = account_info(&keys[2], &mut l2, &mut other_data);
+        let unused = account_info(&keys[3], &mut l3, &mut no_data);
+
+        assert_eq!(
+            swap_accounts(&input, &other, &unused).check_distinct_mints(),
+            Ok(())
+        );
+        assert_eq!(
+            swap_accounts(&input, &same, &unused).check_distinct_mints(),
+            Err(swap_client::error::SwapError::RepeatedMint.into())
+        );
+        // the input account itself as the output
+        assert_eq!(
+            swap_accounts(&input, &input, &unused).check_distinct_mints(),
+            Err(swap_client::error::SwapError::RepeatedMint.into())
+        );
+        // the output isn't a token account
+        assert!(swap_accounts(&input, &unused, &unused)
+            .check_distinct_mints()
+            .is_err());
+    }
+
+    #[test]
+    fn test_swap_with_deadline() {
+        let now = 1_650_000_000;
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            solana_program::sysvar::clock::ID,
+        ];
+        let [mut l0, mut l1, mut l2, mut l3] = [0u64; 4];
+        let mut input_data = token_account_data(&Pubkey::new_unique());
+        let mut output_data = token_account_data(&Pubkey::new_unique());
+        let mut no_data = [];
+        let mut clock_data = vec![0u8; Clock::size_of()];
+        let input = account_info(&keys[0], &mut l0, &mut input_data);
+        let output = account_info(&keys[1], &mut l1, &mut output_data);
+        let unused = account_info(&keys[2], &mut l2, &mut no_data);
+        let mut clock = account_info(&keys[3], &mut l3, &mut clock_data);
+        Clock {
+            unix_timestamp: now,
+            ..Clock::default()
+        }
+        .to_account_info(&mut clock)
+        .unwrap();
+
+        let swap_ctx = || {
+            let mut accounts = swap_accounts(&input, &output, &unused);
+            accounts.user.clock = clock.clone();
+            CpiContext::new(unused.clone(), accounts)
+        };
+        assert_eq!(
+            swap_with_deadline(swap_ctx(), 100, 90, now - 1),
+            Err(swap_client::error::SwapError::DeadlineExceeded.into())
+        );
+        assert_eq!(swap_with_deadline(swap_ctx(), 100, 90, now), Ok(()));
+        // the clock has to be the Clock sysvar
+        let mut accounts = swap_accounts(&input, &output, &unused);
+        accounts.user.clock = input.clone();
+        assert!(
+            swap_with_deadline(CpiContext::new(unused.clone(), accounts), 100, 90, now).is_err()
+        );
+    }
+}
@@ -98,6 +98,9 @@ pub enum SwapError {
     /// Token mint decimals must be the same.
     #[error("Token mints must have same decimals")]
     MismatchedDecimals,
+    /// The swap deadline has passed.
+    #[error("Swap deadline exceeded")]
+    DeadlineExceeded,
 }
 
 impl From<SwapError> for ProgramError {
@@ -173,6 +176,7 @@ impl PrintProgramError for SwapError {
             SwapError::NoActiveTransfer => msg!("Error: No active admin transfer in progress"),
             SwapError::AdminDeadlineExceeded => msg!("Error: Admin transfer deadline exceeded"),
             SwapError::MismatchedDecimals => msg!("Error: Token mints must have same decimals"),
+            SwapError::DeadlineExceeded => msg!("Error: Swap deadline exceeded"),
         }
     }
 }
@@ -5,6 +5,10 @@ use solana_program::{
     program_error::ProgramError,
     program_pack::{Pack, Sealed},
 };
+use std::{convert::TryFrom, fmt};
+
+/// Denominator of a fee expressed in basis points
+pub const BPS_DENOMINATOR: u64 = 10_000;
 
 /// Fees struct
 #[repr(C)]
@@ -29,6 +33,145 @@ pub struct Fees {
     pub withdraw_fee_denominator: u64,
 }
 
+impl Fees {
+    /// Creates fees from basis points, e.g. 30 for a 0.30% trade fee.
+    /// The admin fees are the share of the trade and withdraw fees taken by the admin.
+    pub fn from_bps(
+        trade_bps: u64,
+        admin_bps: u64,
+        withdraw_bps: u64,
+        admin_withdraw_bps: u64,
+    ) -> Fees {
+        Fees {
+            admin_trade_fee_numerator: admin_bps,
+            admin_trade_fee_denominator: BPS_DENOMINATOR,
+            admin_withdraw_fee_numerator: admin_withdraw_bps,
+            admin_withdraw_fee_denominator: BPS_DENOMINATOR,
+            trade_fee_numerator: trade_bps,
+            trade_fee_denominator: BPS_DENOMINATOR,
+            withdraw_fee_numerator: withdraw_bps,
+            withdraw_fee_denominator: BPS_DENOMINATOR,
+        }
+    }
+
+    /// Trade fee in basis points, rounded down. 0 if the denominator is 0.
+    pub fn trade_fee_bps(&self) -> u64 {
+        to_bps(self.trade_fee_numerator, self.trade_fee_denominator)
+    }
+
+    /// The eight fields as little endian u64s in declaration order, the [Pack] layout,
+    /// for passing fees between programs in instruction data without Borsh.
+    pub fn to_compact_bytes(&self) -> [u8; Fees::LEN] {
+        let mut output = [0u8; Fees::LEN];
+        self.pack_into_slice(&mut output);
+        output
+    }
+
+    /// Decodes [Fees::to_compact_bytes]. Returns `None` unless `input` is exactly
+    /// [Fees::LEN] bytes long.
+    pub fn from_compact_bytes(input: &[u8]) -> Option<Fees> {
+        if input.len() != Fees::LEN {
+            return None;
+        }
+        Fees::unpack_from_slice(input).ok()
+    }
+
+    /// Whether every fee is at most its maximum in `max`, compared exactly by
+    /// cross-multiplication rather than after rounding to basis points.
+    /// A fee with a zero denominator is never within bounds.
+    pub fn within_bounds(&self, max: &FeeBounds) -> bool {
+        [
+            (
+                self.trade_fee_numerator,
+                self.trade_fee_denominator,
+                max.max_trade_bps,
+            ),
+            (
+                self.admin_trade_fee_numerator,
+                self.admin_trade_fee_denominator,
+                max.max_admin_trade_bps,
+            ),
+            (
+                self.withdraw_fee_numerator,
+                self.withdraw_fee_denominator,
+                max.max_withdraw_bps,
+            ),
+            (
+                self.admin_withdraw_fee_numerator,
+                self.admin_withdraw_fee_denominator,
+                max.max_admin_withdraw_bps,
+            ),
+        ]
+        .iter()
+        .all(|(numerator, denominator, max_bps)| {
+            // numerator / denominator <= max_bps / BPS_DENOMINATOR
+            *denominator != 0
+                && *numerator as u128 * BPS_DENOMINATOR as u128
+                    <= *max_bps as u128 * *denominator as u128
+        })
+    }
+}
+
+/// Maximum fees allowed by governance, in basis points, checked with [Fees::within_bounds].
+/// The admin maximums are shares of the trade and withdraw fees, as in [Fees::from_bps].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeBounds {
+    /// Max trade fee
+    pub max_trade_bps: u64,
+    /// Max admin share of the trade fee
+    pub max_admin_trade_bps: u64,
+    /// Max withdraw fee
+    pub max_withdraw_bps: u64,
+    /// Max admin share of the withdraw fee
+    pub max_admin_withdraw_bps: u64,
+}
+
+/// `numerator / denominator` in basis points, rounded down. 0 if the denominator is 0.
+fn to_bps(numerator: u64, denominator: u64) -> u64 {
+    let bps = (numerator as u128 * BPS_DENOMINATOR as u128)
+        .checked_div(denominator.into())
+        .unwrap_or(0);
+    u64::try_from(bps).unwrap_or(u64::MAX)
+}
+
+/// Fees as percentages rounded down to two decimals, e.g.
+/// `trade: 0.30%, admin trade: 50.00%, withdraw: 0.10%, admin withdraw: 0.00%`.
+/// The admin fees are shares of the trade and withdraw fees.
+impl fmt::Display for Fees {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fees = [
+            (
+                "trade",
+                self.trade_fee_numerator,
+                self.trade_fee_denominator,
+            ),
+            (
+                "admin trade",
+                self.admin_trade_fee_numerator,
+                self.admin_trade_fee_denominator,
+            ),
+            (
+                "withdraw",
+                self.withdraw_fee_numerator,
+                self.withdraw_fee_denominator,
+            ),
+            (
+                "admin withdraw",
+                self.admin_withdraw_fee_numerator,
+                self.admin_withdraw_fee_denominator,
+            ),
+        ];
+        for (i, (name, numerator, denominator)) in fees.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            let bps = to_bps(*numerator, *denominator);
+            write!(f, "{}: {}.{:02}%", name, bps / 100, bps % 100)?;
+        }
+        Ok(())
+    }
+}
+
 impl Sealed for Fees {}
 impl Pack for Fees {
     const LEN: usize = 64;
@@ -123,4 +266,105 @@ mod tests {
         let unpacked = Fees::unpack_from_slice(&packed).unwrap();
         assert_eq!(fees, unpacked);
     }
+
+    #[test]
+    fn fees_from_bps() {
+        let fees = Fees::from_bps(30, 5_000, 10, 0);
+        assert_eq!(fees.trade_fee_numerator, 30);
+        assert_eq!(fees.trade_fee_denominator, BPS_DENOMINATOR);
+        assert_eq!(fees.admin_trade_fee_numerator, 5_000);
+        assert_eq!(fees.withdraw_fee_numerator, 10);
+        assert_eq!(fees.admin_withdraw_fee_numerator, 0);
+        assert_eq!(fees.admin_withdraw_fee_denominator, BPS_DENOMINATOR);
+        assert_eq!(fees.trade_fee_bps(), 30);
+
+        let fees = Fees {
+            trade_fee_numerator: 3,
+            trade_fee_denominator: 1_000,
+            ..Fees::default()
+        };
+        assert_eq!(fees.trade_fee_bps(), 30);
+        assert_eq!(Fees::default().trade_fee_bps(), 0);
+    }
+
+    #[test]
+    fn fees_compact_bytes() {
+        let fees = Fees::from_bps(30, 5_000, 10, 2_500);
+        let bytes = fees.to_compact_bytes();
+        assert_eq!(Fees::from_compact_bytes(&bytes), Some(fees));
+        assert_eq!(&bytes[32..40], &30u64.to_le_bytes());
+        assert_eq!(&bytes[40..48], &BPS_DENOMINATOR.to_le_bytes());
+
+        let mut packed = [0u8; Fees::LEN];
+        fees.pack_into_slice(&mut packed);
+        assert_eq!(bytes, packed);
+        assert_eq!(
+            Fees::from_compact_bytes(&Fees::default().to_compact_bytes()),
+            Some(Fees::default())
+        );
+
+        assert_eq!(Fees::from_compact_bytes(&bytes[..Fees::LEN - 1]), None);
+        assert_eq!(Fees::from_compact_bytes(&[bytes, bytes].concat()), None);
+        assert_eq!(Fees::from_compact_bytes(&[]), None);
+    }
+
+    #[test]
+    fn fees_within_bounds() {
+        let max = FeeBounds {
+            max_trade_bps: 100,
+            max_admin_trade_bps: 5_000,
+            max_withdraw_bps: 50,
+            max_admin_withdraw_bps: 5_000,
+        };
+        assert!(Fees::from_bps(30, 1_000, 10, 0).within_bounds(&max));
+        // exactly at the caps
+        assert!(Fees::from_bps(100, 5_000, 50, 5_000).within_bounds(&max));
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            ..Fees::from_bps(0, 0, 0, 0)
+        };
+        assert!(fees.within_bounds(&max));
+
+        // just over a cap, including by less than a basis point
+        assert!(!Fees::from_bps(101, 5_000, 50, 5_000).within_bounds(&max));
+        assert!(!Fees::from_bps(100, 5_001, 50, 5_000).within_bounds(&max));
+        assert!(!Fees::from_bps(100, 5_000, 51, 5_000).within_bounds(&max));
+        assert!(!Fees::from_bps(100, 5_000, 50, 5_001).within_bounds(&max));
+        let fees = Fees {
+            trade_fee_numerator: 1_000_001,
+            trade_fee_denominator: 100_000_000,
+            ..Fees::from_bps(0, 0, 0, 0)
+        };
+        assert!(!fees.within_bounds(&max));
+
+        assert!(!Fees::default().within_bounds(&max));
+        let fees = Fees {
+            trade_fee_numerator: u64::MAX,
+            trade_fee_denominator: u64::MAX,
+            ..Fees::from_bps(0, 0, 0, 0)
+        };
+        assert!(!fees.within_bounds(&max));
+    }
+
+    #[test]
+    fn display_fees() {
+        assert_eq!(
+            Fees::from_bps(30, 5_000, 10, 0).to_string(),
+            "trade: 0.30%, admin trade: 50.00%, withdraw: 0.10%, admin withdraw: 0.00%"
+        );
+        assert_eq!(
+            Fees::default().to_string(),
+            "trade: 0.00%, admin trade: 0.00%, withdraw: 0.00%, admin withdraw: 0.00%"
+        );
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 3,
+            ..Fees::from_bps(0, 10_000, 0, 0)
+        };
+        assert_eq!(
+            fees.to_string(),
+            "trade: 33.33%, admin trade: 100.00%, withdraw: 0.00%, admin withdraw: 0.00%"
+        );
+    }
 }
@@ -38,6 +38,16 @@ pub struct SwapData {
     pub minimum_amount_out: u64,
 }
 
+/// Checks that a swap with the given `deadline` may still execute at the current
+/// unix timestamp `now`, taken from the Clock sysvar. The deadline itself is still valid.
+/// The swap instruction carries no deadline, so it is checked by the caller before invoking it.
+pub fn check_deadline(now: i64, deadline: i64) -> Result<(), SwapError> {
+    if now > deadline {
+        return Err(SwapError::DeadlineExceeded);
+    }
+    Ok(())
+}
+
 /// Deposit instruction data
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq)]
@@ -978,4 +988,19 @@ mod tests {
         let unpacked = SwapInstruction::unpack(&expect).unwrap();
         assert_eq!(unpacked, check);
     }
+
+    #[test]
+    fn test_check_deadline() {
+        let deadline = 1_650_000_000;
+        assert_eq!(check_deadline(deadline - 60, deadline), Ok(()));
+        assert_eq!(check_deadline(deadline, deadline), Ok(()));
+        assert_eq!(
+            check_deadline(deadline + 1, deadline),
+            Err(SwapError::DeadlineExceeded)
+        );
+        assert_eq!(
+            check_deadline(i64::MAX, deadline),
+            Err(SwapError::DeadlineExceeded)
+        );
+    }
 }
@@ -180,6 +180,62 @@ impl Pack for SwapInfo {
     }
 }
 
+/// Lifetime trading statistics of a swap.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolStats {
+    /// Initialized state
+    pub is_initialized: bool,
+    /// Public key of the swap these statistics belong to
+    pub swap: Pubkey,
+    /// Total amount of tokens swapped in, across both tokens
+    pub volume: u128,
+}
+
+impl PoolStats {
+    /// Adds a swap of `amount_in` tokens to the volume.
+    /// Returns `None`, leaving the volume untouched, if it would overflow.
+    pub fn add_volume(&mut self, amount_in: u64) -> Option<()> {
+        self.volume = self.volume.checked_add(u128::from(amount_in))?;
+        Some(())
+    }
+}
+
+impl Sealed for PoolStats {}
+impl IsInitialized for PoolStats {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for PoolStats {
+    const LEN: usize = 49;
+
+    /// Unpacks a byte buffer into a [PoolStats](struct.PoolStats.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 49];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (is_initialized, swap, volume) = array_refs![input, 1, 32, 16];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            swap: Pubkey::new_from_array(*swap),
+            volume: u128::from_le_bytes(*volume),
+        })
+    }
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 49];
+        let (is_initialized, swap, volume) = mut_array_refs![output, 1, 32, 16];
+        is_initialized[0] = self.is_initialized as u8;
+        swap.copy_from_slice(self.swap.as_ref());
+        *volume = self.volume.to_le_bytes();
+    }
+}
+
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
@@ -294,4 +350,43 @@ mod tests {
         let unpacked = SwapInfo::unpack(&packed).unwrap();
         assert_eq!(swap_info, unpacked);
     }
+
+    #[test]
+    fn test_pool_stats() {
+        let mut stats = PoolStats {
+            is_initialized: true,
+            swap: Pubkey::new_from_array([1u8; 32]),
+            volume: 0,
+        };
+        for _ in 0..1_000 {
+            stats.add_volume(u64::MAX).unwrap();
+        }
+        assert_eq!(stats.volume, 1_000 * u128::from(u64::MAX));
+
+        let mut packed = [0u8; PoolStats::LEN];
+        PoolStats::pack(stats, &mut packed).unwrap();
+        assert_eq!(PoolStats::unpack(&packed).unwrap(), stats);
+
+        stats.volume = u128::MAX - 1;
+        assert!(stats.add_volume(2).is_none());
+        assert_eq!(stats.volume, u128::MAX - 1);
+        PoolStats::pack(stats, &mut packed).unwrap();
+
+        let mut expect = vec![1u8];
+        expect.extend_from_slice(&[1u8; 32]);
+        expect.extend_from_slice(&(u128::MAX - 1).to_le_bytes());
+        assert_eq!(packed.to_vec(), expect);
+
+        packed[0] = 2;
+        assert_eq!(
+            PoolStats::unpack_unchecked(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        stats.add_volume(1).unwrap();
+        assert_eq!(stats.volume, u128::MAX);
+        assert!(stats.add_volume(1).is_none());
+        stats.add_volume(0).unwrap();
+        assert_eq!(stats.volume, u128::MAX);
+    }
 }
@@ -4,14 +4,17 @@
 #![allow(clippy::ptr_offset_with_cast)]
 #![allow(clippy::manual_range_contains)]
 
+#[cfg(feature = "std")]
 use borsh::{BorshDeserialize, BorshSerialize};
-use swap_client::error::SwapError;
-use std::borrow::BorrowMut;
-use std::convert::TryInto;
+use core::convert::TryInto;
+#[cfg(feature = "std")]
+use core::mem::size_of;
+#[cfg(feature = "std")]
 use std::io::{Error, ErrorKind, Write};
-use std::mem::size_of;
+use swap_client::error::SwapError;
 use uint::construct_uint;
 
+#[cfg(feature = "std")]
 macro_rules! impl_borsh_serialize_for_bn {
     ($type: ident) => {
         impl BorshSerialize for $type {
@@ -24,6 +27,7 @@ macro_rules! impl_borsh_serialize_for_bn {
     };
 }
 
+#[cfg(feature = "std")]
 macro_rules! impl_borsh_deserialize_for_bn {
     ($type: ident) => {
         impl BorshDeserialize for $type {
@@ -76,16 +80,15 @@ impl U256 {
 
     /// Convert to little endian bytes
     pub fn to_le_bytes(self) -> [u8; 32] {
-        let mut buf: Vec<u8> = Vec::with_capacity(size_of::<Self>());
-        self.to_little_endian(buf.borrow_mut());
-
         let mut bytes: [u8; 32] = [0u8; 32];
-        bytes.copy_from_slice(buf.as_slice());
+        self.to_little_endian(&mut bytes);
         bytes
     }
 }
 
+#[cfg(feature = "std")]
 impl_borsh_deserialize_for_bn!(U256);
+#[cfg(feature = "std")]
 impl_borsh_serialize_for_bn!(U256);
 
 construct_uint! {
@@ -121,14 +124,13 @@ impl U192 {
 
     /// Convert to little endian bytes
     pub fn to_le_bytes(self) -> [u8; 24] {
-        let mut buf: Vec<u8> = Vec::with_capacity(size_of::<Self>());
-        self.to_little_endian(buf.borrow_mut());
-
         let mut bytes: [u8; 24] = [0u8; 24];
-        bytes.copy_from_slice(buf.as_slice());
+        self.to_little_endian(&mut bytes);
         bytes
     }
 }
 
+#[cfg(feature = "std")]
 impl_borsh_deserialize_for_bn!(U192);
+#[cfg(feature = "std")]
 impl_borsh_serialize_for_bn!(U192);
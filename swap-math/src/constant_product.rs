@@ -0,0 +1,1158 @@
+//! Constant product (x * y = k) swap calculations
+
+use core::cmp::Ordering;
+
+use num_traits::ToPrimitive;
+use swap_client::fees::Fees;
+
+use crate::{
+    curve::SwapResult,
+    decimal::Decimal,
+    error::MathError,
+    math::{percent_of, sqrt_u128, FeeCalculator},
+};
+
+/// Computes the amount of destination token received for `amount_in`
+/// before any fees are applied.
+/// Equation:
+/// dy = y * dx / (x + dx)
+pub fn constant_product_out(amount_in: u64, reserve_in: u64, reserve_out: u64) -> Option<u64> {
+    (reserve_out as u128)
+        .checked_mul(amount_in.into())?
+        .checked_div((reserve_in as u128).checked_add(amount_in.into())?)?
+        .to_u64()
+}
+
+/// [constant_product_out], returning why the calculation failed.
+pub fn constant_product_out_checked(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+) -> Result<u64, MathError> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(MathError::EmptyReserve);
+    }
+    constant_product_out(amount_in, reserve_in, reserve_out).ok_or(MathError::Overflow)
+}
+
+/// Computes the amount of source token required to receive `amount_out`
+/// before any fees are applied, rounded up.
+/// Equation:
+/// dx = x * dy / (y - dy)
+pub fn constant_product_in(amount_out: u64, reserve_in: u64, reserve_out: u64) -> Option<u64> {
+    let denominator = (reserve_out as u128).checked_sub(amount_out.into())?;
+    (reserve_in as u128)
+        .checked_mul(amount_out.into())?
+        .checked_add(denominator.checked_sub(1)?)?
+        .checked_div(denominator)?
+        .to_u64()
+}
+
+/// [constant_product_in], returning why the calculation failed.
+pub fn constant_product_in_checked(
+    amount_out: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+) -> Result<u64, MathError> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(MathError::EmptyReserve);
+    }
+    if amount_out >= reserve_out {
+        return Err(MathError::InsufficientLiquidity);
+    }
+    constant_product_in(amount_out, reserve_in, reserve_out).ok_or(MathError::Overflow)
+}
+
+/// Basis points in 100%.
+const BPS: u128 = 10_000;
+
+/// Computes how much worse than the spot price `amount_in` is executed at,
+/// in basis points and before fees, rounded up.
+/// Equation:
+/// impact = 1 - (dy / dx) / (y / x) = dx / (x + dx)
+pub fn price_impact_bps(amount_in: u64, reserve_in: u64, reserve_out: u64) -> Option<u64> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return None;
+    }
+    let denominator = (reserve_in as u128).checked_add(amount_in.into())?;
+    (amount_in as u128)
+        .checked_mul(BPS)?
+        .checked_add(denominator.checked_sub(1)?)?
+        .checked_div(denominator)?
+        .to_u64()
+}
+
+/// Finds the largest `amount_in` whose [price_impact_bps] is at most `max_impact_bps`.
+pub fn max_amount_in_for_impact(
+    reserve_in: u64,
+    reserve_out: u64,
+    max_impact_bps: u64,
+) -> Option<u64> {
+    let within = |amount_in| {
+        price_impact_bps(amount_in, reserve_in, reserve_out).map(|bps| bps <= max_impact_bps)
+    };
+    if within(u64::MAX)? {
+        return Some(u64::MAX);
+    }
+    // Binary search: `low` is always within the threshold, `high` never is
+    let mut low = 0;
+    let mut high = u64::MAX;
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if within(mid)? {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Some(low)
+}
+
+/// Finds the smallest `amount_in` after which the spot price, in source tokens per
+/// destination token, is at least `target_price`, before fees.
+/// Returns `None` if the target is below the current spot price or can't be reached.
+pub fn amount_in_to_reach_price(
+    reserve_in: u64,
+    reserve_out: u64,
+    target_price: Decimal,
+) -> Option<u64> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return None;
+    }
+    let scale = 10u128.checked_pow(target_price.decimals)?;
+    // Compares the spot price after `amount_in` to the target
+    let compare = |amount_in: u64| {
+        let new_reserve_in = reserve_in.checked_add(amount_in)?;
+        let new_reserve_out =
+            reserve_out.checked_sub(constant_product_out(amount_in, reserve_in, reserve_out)?)?;
+        Some(
+            (new_reserve_in as u128)
+                .checked_mul(scale)?
+                .cmp(&target_price.value.checked_mul(new_reserve_out.into())?),
+        )
+    };
+    match compare(0)? {
+        Ordering::Greater => return None,
+        Ordering::Equal => return Some(0),
+        Ordering::Less => {}
+    }
+    let max_amount_in = u64::MAX - reserve_in;
+    if compare(max_amount_in)? == Ordering::Less {
+        return None;
+    }
+    // Binary search: `low` never reaches the target, `high` always does
+    let mut low = 0;
+    let mut high = max_amount_in;
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if compare(mid)? == Ordering::Less {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Some(high)
+}
+
+/// Compute SwapResult after an exchange of exactly `amount_in`.
+/// Fees are taken from the destination amount, as in [crate::curve::StableSwap::swap_to].
+pub fn swap_exact_in(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fees: &Fees,
+) -> Option<SwapResult> {
+    let dy = constant_product_out(amount_in, reserve_in, reserve_out)?;
+    let dy_fee = fees.trade_fee(dy)?;
+    let admin_fee = fees.admin_trade_fee(dy_fee)?;
+
+    let amount_swapped = dy.checked_sub(dy_fee)?;
+    let new_destination_amount = reserve_out
+        .checked_sub(amount_swapped)?
+        .checked_sub(admin_fee)?;
+    let new_source_amount = reserve_in.checked_add(amount_in)?;
+
+    Some(SwapResult {
+        new_source_amount,
+        new_destination_amount,
+        amount_swapped,
+        admin_fee,
+        fee: dy_fee,
+    })
+}
+
+/// Whether a pool holds enough of both tokens to quote, i.e. neither reserve is below
+/// `min_liquidity`. Routers should skip pools that aren't, as near-empty reserves give
+/// absurd prices. An empty pool is never tradeable.
+pub fn is_tradeable(reserve_in: u64, reserve_out: u64, min_liquidity: u64) -> bool {
+    reserve_in >= min_liquidity.max(1) && reserve_out >= min_liquidity.max(1)
+}
+
+/// Whether `amount_in` is too small to receive anything once the curve and the trade
+/// fee have rounded down, so the swap would only donate it to the pool.
+/// Returns `false` if the swap can't be computed, which [swap_exact_in] reports itself.
+pub fn is_dust_swap(amount_in: u64, reserve_in: u64, reserve_out: u64, fees: &Fees) -> bool {
+    matches!(
+        swap_exact_in(amount_in, reserve_in, reserve_out, fees),
+        Some(result) if result.amount_swapped == 0
+    )
+}
+
+/// New `(reserve_in, reserve_out)` after a swap of `amount_in` for `amount_out`.
+/// Returns `None` instead of wrapping when the swap would overdraw `reserve_out`
+/// or overflow `reserve_in`, e.g. from a rounding bug in the amounts.
+pub fn apply_swap_to_reserves(
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_in: u64,
+    amount_out: u64,
+) -> Option<(u64, u64)> {
+    Some((
+        reserve_in.checked_add(amount_in)?,
+        reserve_out.checked_sub(amount_out)?,
+    ))
+}
+
+/// Splits `total_in` into `chunks` equal amounts, with the remainder added to the last one.
+/// Returns no chunks if `chunks` is 0.
+#[cfg(feature = "std")]
+pub fn split_swap(total_in: u64, chunks: u32) -> Vec<u64> {
+    if chunks == 0 {
+        return Vec::new();
+    }
+    let chunk = total_in / u64::from(chunks);
+    let mut amounts = vec![chunk; chunks as usize];
+    if let Some(last) = amounts.last_mut() {
+        *last += total_in % u64::from(chunks);
+    }
+    amounts
+}
+
+/// Simulates swapping each of `chunks` in turn with [swap_exact_in], with each chunk
+/// executed against the reserves the previous one left, and returns the total received.
+pub fn split_swap_out(
+    chunks: &[u64],
+    reserve_in: u64,
+    reserve_out: u64,
+    fees: &Fees,
+) -> Option<u64> {
+    let mut reserves = (reserve_in, reserve_out);
+    let mut total_out: u64 = 0;
+    for amount_in in chunks {
+        let result = swap_exact_in(*amount_in, reserves.0, reserves.1, fees)?;
+        reserves = (result.new_source_amount, result.new_destination_amount);
+        total_out = total_out.checked_add(result.amount_swapped)?;
+    }
+    Some(total_out)
+}
+
+/// [swap_exact_in] for as much of `amount_in` as the pool can execute, returning the
+/// amount filled with its result.
+/// Past the input that takes the destination reserve down to a single token, more
+/// input buys nothing, so the fill stops there. It is also capped so the source
+/// reserve never exceeds `u64::MAX`.
+pub fn swap_exact_in_partial(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fees: &Fees,
+) -> Option<(u64, SwapResult)> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return None;
+    }
+    let max_in = constant_product_in(reserve_out.checked_sub(1)?, reserve_in, reserve_out)
+        .unwrap_or(u64::MAX)
+        .min(u64::MAX.checked_sub(reserve_in)?);
+    let filled_in = amount_in.min(max_in);
+    let result = swap_exact_in(filled_in, reserve_in, reserve_out, fees)?;
+    Some((filled_in, result))
+}
+
+/// Compute SwapResult for an exchange receiving at least `amount_out` after fees,
+/// the inverse of [swap_exact_in].
+/// The destination amount is grossed up to cover the trade fee and the source amount
+/// needed for it is rounded up, so `amount_swapped` can exceed `amount_out` by rounding.
+/// Equation:
+/// dy = amount_out * fee_denominator / (fee_denominator - fee_numerator)
+pub fn swap_exact_out(
+    amount_out: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fees: &Fees,
+) -> Option<SwapResult> {
+    // No input covers a trade fee of 100% or more
+    let net_denominator = fees
+        .trade_fee_denominator
+        .checked_sub(fees.trade_fee_numerator)?;
+    let dy = (amount_out as u128)
+        .checked_mul(fees.trade_fee_denominator.into())?
+        .checked_add(net_denominator.checked_sub(1)?.into())?
+        .checked_div(net_denominator.into())?
+        .to_u64()?;
+    let dx = constant_product_in(dy, reserve_in, reserve_out)?;
+    swap_exact_in(dx, reserve_in, reserve_out, fees)
+}
+
+/// Pool tokens of the first deposit locked forever, so the supply can't be brought down
+/// to a few tokens each worth a fortune, inflating away later deposits.
+/// The pool mints them along with the first deposit, to an account nobody can spend from.
+pub const MINIMUM_LIQUIDITY: u64 = 1_000;
+
+/// Computes the pool tokens minted to the depositor for depositing `amount_a` and `amount_b`.
+/// The first deposit, into a pool without supply, mints `sqrt(amount_a * amount_b)`
+/// less the [MINIMUM_LIQUIDITY] locked forever, and fails if that leaves nothing.
+/// The locked tokens are minted by the caller, so the supply after the first deposit is
+/// the returned amount plus [MINIMUM_LIQUIDITY], and `pool_token_supply` of every later
+/// deposit includes them.
+/// Later deposits mint in proportion to the smaller of their shares of the reserves.
+pub fn deposit_lp_tokens(
+    amount_a: u64,
+    amount_b: u64,
+    reserve_a: u64,
+    reserve_b: u64,
+    pool_token_supply: u64,
+) -> Option<u64> {
+    let minted = if pool_token_supply == 0 {
+        sqrt_u128((amount_a as u128).checked_mul(amount_b.into())?)
+            .to_u64()?
+            .checked_sub(MINIMUM_LIQUIDITY)?
+    } else {
+        let share = |amount: u64, reserve: u64| {
+            (amount as u128)
+                .checked_mul(pool_token_supply.into())?
+                .checked_div(reserve.into())?
+                .to_u64()
+        };
+        share(amount_a, reserve_a)?.min(share(amount_b, reserve_b)?)
+    };
+    if minted == 0 {
+        return None;
+    }
+    Some(minted)
+}
+
+/// Computes the pool tokens `deposit_amounts` mint, see [deposit_lp_tokens], and the
+/// minimum to accept for them, `slippage_bps` less and rounded down, to pass as the
+/// deposit's minimum mint amount so it can't be sandwiched.
+/// Returns `(expected, min)`, or `None` if `slippage_bps` is over 100%.
+pub fn deposit_lp_tokens_with_slippage(
+    deposit_amounts: &[u64],
+    reserves: &[u64],
+    lp_supply: u64,
+    slippage_bps: u64,
+) -> Option<(u64, u64)> {
+    let expected = match (deposit_amounts, reserves) {
+        ([amount_a, amount_b], [reserve_a, reserve_b]) => {
+            deposit_lp_tokens(*amount_a, *amount_b, *reserve_a, *reserve_b, lp_supply)?
+        }
+        _ => return None,
+    };
+    let bps = BPS as u64;
+    let min = percent_of(expected, bps.checked_sub(slippage_bps)?, bps)?;
+    Some((expected, min))
+}
+
+/// Share of the pool owned by `deposit_lp` freshly minted pool tokens, in basis points
+/// of `lp_supply_after`, the supply including them, rounded down.
+/// Returns `None` if the deposit is larger than the supply.
+pub fn deposit_share_bps(deposit_lp: u64, lp_supply_after: u64) -> Option<u64> {
+    if deposit_lp > lp_supply_after {
+        return None;
+    }
+    (deposit_lp as u128)
+        .checked_mul(BPS)?
+        .checked_div(lp_supply_after.into())?
+        .to_u64()
+}
+
+/// Quotes swapping `amount_in` through two pools, e.g. A -> B -> C, where each pool is
+/// given as `(reserve_in, reserve_out, fees)`. The first pool's output, net of fees,
+/// is the second pool's input.
+/// Returns `None` if a pool has an empty reserve or a hop outputs nothing.
+pub fn quote_two_hop(
+    amount_in: u64,
+    pool1: (u64, u64, &Fees),
+    pool2: (u64, u64, &Fees),
+) -> Option<u64> {
+    let mut amount = amount_in;
+    for (reserve_in, reserve_out, fees) in [pool1, pool2].iter() {
+        if *reserve_in == 0 || *reserve_out == 0 {
+            return None;
+        }
+        amount = swap_exact_in(amount, *reserve_in, *reserve_out, fees)?.amount_swapped;
+        if amount == 0 {
+            return None;
+        }
+    }
+    Some(amount)
+}
+
+/// Computes the minimum output to accept for a route of `hops` swaps, e.g. [quote_two_hop],
+/// expected to output `expected_out`, allowing `per_hop_bps` of slippage on every hop.
+/// The tolerance compounds, each hop may lose `per_hop_bps` of what the previous one
+/// delivered, rounding down at every hop.
+/// Returns `None` if `per_hop_bps` is over 100%.
+pub fn min_out_multi_hop(expected_out: u64, hops: u32, per_hop_bps: u64) -> Option<u64> {
+    let bps = BPS as u64;
+    let kept_bps = bps.checked_sub(per_hop_bps)?;
+    let mut min_out = expected_out;
+    for _ in 0..hops {
+        min_out = percent_of(min_out, kept_bps, bps)?;
+    }
+    Some(min_out)
+}
+
+/// A constant product pool's reserves, fees and token decimals, for quoting
+/// either direction without passing them around separately.
+///
+/// Quotes are priced on the reserves in their smallest units. `x * y = k` doesn't depend on
+/// the tokens' scales, so the decimals are only for displaying amounts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolState {
+    /// Reserve of token A, in its smallest units
+    pub reserve_a: u64,
+    /// Reserve of token B, in its smallest units
+    pub reserve_b: u64,
+    /// Fees charged on swaps
+    pub fees: Fees,
+    /// Decimals of token A, for display only
+    pub decimals_a: u32,
+    /// Decimals of token B, for display only
+    pub decimals_b: u32,
+}
+
+impl PoolState {
+    /// Quotes a swap of `amount_in` token A for token B, see [swap_exact_in].
+    /// `new_source_amount` is the new A reserve and `new_destination_amount` the new B reserve.
+    pub fn quote_a_to_b(&self, amount_in: u64) -> Option<SwapResult> {
+        swap_exact_in(amount_in, self.reserve_a, self.reserve_b, &self.fees)
+    }
+
+    /// Quotes a swap of `amount_in` token B for token A, see [swap_exact_in].
+    /// `new_source_amount` is the new B reserve and `new_destination_amount` the new A reserve.
+    pub fn quote_b_to_a(&self, amount_in: u64) -> Option<SwapResult> {
+        swap_exact_in(amount_in, self.reserve_b, self.reserve_a, &self.fees)
+    }
+}
+
+/// Quotes swapping `amount_in` token A for token B in each of `pools`, all for the same
+/// pair, see [PoolState::quote_a_to_b], and returns the index and quote of the pool paying
+/// out the most. Ties go to the first such pool and pools that can't quote are skipped.
+/// Returns `None` if no pool can quote.
+pub fn best_quote(amount_in: u64, pools: &[PoolState]) -> Option<(usize, SwapResult)> {
+    let mut best: Option<(usize, SwapResult)> = None;
+    for (index, pool) in pools.iter().enumerate() {
+        let result = match pool.quote_a_to_b(amount_in) {
+            Some(result) => result,
+            None => continue,
+        };
+        let is_better = match &best {
+            Some((_, best_result)) => result.amount_swapped > best_result.amount_swapped,
+            None => true,
+        };
+        if is_better {
+            best = Some((index, result));
+        }
+    }
+    best
+}
+
+/// Computes the price paid for `amount_in`, in source tokens per destination token,
+/// including trade fees.
+pub fn effective_price(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fees: &Fees,
+    decimals: u32,
+) -> Option<Decimal> {
+    let result = swap_exact_in(amount_in, reserve_in, reserve_out, fees)?;
+    let value = (amount_in as u128)
+        .checked_mul(10u128.checked_pow(decimals)?)?
+        .checked_div(result.amount_swapped.into())?;
+    Some(Decimal::new(value, decimals))
+}
+
+/// Computes the marginal price, in source tokens per destination token, before fees.
+/// Equation:
+/// p = x / y
+pub fn spot_price(reserve_in: u64, reserve_out: u64, decimals: u32) -> Option<Decimal> {
+    let value = (reserve_in as u128)
+        .checked_mul(10u128.checked_pow(decimals)?)?
+        .checked_div(reserve_out.into())?;
+    Some(Decimal::new(value, decimals))
+}
+
+/// Computes the [spot_price] in both directions, `(price_a_per_b, price_b_per_a)`, i.e.
+/// token A per token B and token B per token A. The two are reciprocals up to rounding.
+pub fn marginal_prices(
+    reserve_a: u64,
+    reserve_b: u64,
+    decimals: u32,
+) -> Option<(Decimal, Decimal)> {
+    Some((
+        spot_price(reserve_a, reserve_b, decimals)?,
+        spot_price(reserve_b, reserve_a, decimals)?,
+    ))
+}
+
+/// Computes the [spot_price] once `amount_in` has been swapped, before fees.
+pub fn spot_price_after(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    decimals: u32,
+) -> Option<Decimal> {
+    let amount_out = constant_product_out(amount_in, reserve_in, reserve_out)?;
+    spot_price(
+        reserve_in.checked_add(amount_in)?,
+        reserve_out.checked_sub(amount_out)?,
+        decimals,
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::pool_converter::PoolTokenConverter;
+    use proptest::prelude::*;
+
+    const RESERVE: u64 = 1_000_000_000;
+
+    /// [constant_product_out] derived from the invariant instead: the smallest destination
+    /// reserve keeping `x * y` after the swap, `ceil(x * y / (x + dx))`, subtracted from `y`.
+    fn reference_constant_product_out(
+        amount_in: u64,
+        reserve_in: u64,
+        reserve_out: u64,
+    ) -> Option<u64> {
+        let invariant = reserve_in as u128 * reserve_out as u128;
+        let new_reserve_in = reserve_in as u128 + amount_in as u128;
+        if new_reserve_in == 0 {
+            return None;
+        }
+        let mut new_reserve_out = invariant / new_reserve_in;
+        if new_reserve_out * new_reserve_in < invariant {
+            new_reserve_out += 1;
+        }
+        (reserve_out as u128 - new_reserve_out).to_u64()
+    }
+
+    fn fees() -> Fees {
+        Fees {
+            admin_trade_fee_numerator: 0,
+            admin_trade_fee_denominator: 1,
+            admin_withdraw_fee_numerator: 0,
+            admin_withdraw_fee_denominator: 1,
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10_000,
+            withdraw_fee_numerator: 0,
+            withdraw_fee_denominator: 1,
+        }
+    }
+
+    #[test]
+    fn test_checked_errors() {
+        assert_eq!(
+            constant_product_out_checked(1_000, RESERVE, RESERVE),
+            Ok(999)
+        );
+        assert_eq!(
+            constant_product_out_checked(1_000, 0, RESERVE),
+            Err(MathError::EmptyReserve)
+        );
+        assert_eq!(
+            constant_product_out_checked(1_000, RESERVE, 0),
+            Err(MathError::EmptyReserve)
+        );
+
+        assert_eq!(
+            constant_product_in_checked(999, RESERVE, RESERVE),
+            Ok(1_000)
+        );
+        assert_eq!(
+            constant_product_in_checked(RESERVE, RESERVE, RESERVE),
+            Err(MathError::InsufficientLiquidity)
+        );
+        assert_eq!(
+            constant_product_in_checked(1, 0, RESERVE),
+            Err(MathError::EmptyReserve)
+        );
+        assert_eq!(
+            constant_product_in_checked(u64::MAX - 1, u64::MAX, u64::MAX),
+            Err(MathError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_max_amount_in_for_impact() {
+        assert_eq!(price_impact_bps(0, RESERVE, RESERVE), Some(0));
+        // 1% of the reserve moves the price by ~99.01 bps, rounded up
+        assert_eq!(price_impact_bps(RESERVE / 100, RESERVE, RESERVE), Some(100));
+        assert_eq!(price_impact_bps(RESERVE, RESERVE, RESERVE), Some(5_000));
+        assert_eq!(price_impact_bps(1, 0, RESERVE), None);
+
+        for max_impact_bps in [1, 30, 100, 5_000, 9_999].iter() {
+            let amount_in = max_amount_in_for_impact(RESERVE, RESERVE, *max_impact_bps).unwrap();
+            assert!(price_impact_bps(amount_in, RESERVE, RESERVE).unwrap() <= *max_impact_bps);
+            assert!(price_impact_bps(amount_in + 1, RESERVE, RESERVE).unwrap() > *max_impact_bps);
+        }
+        assert_eq!(max_amount_in_for_impact(RESERVE, RESERVE, 0), Some(0));
+        assert_eq!(
+            max_amount_in_for_impact(RESERVE, RESERVE, 10_000),
+            Some(u64::MAX)
+        );
+        assert_eq!(max_amount_in_for_impact(RESERVE, 0, 100), None);
+    }
+
+    #[test]
+    fn test_amount_in_to_reach_price() {
+        let spot_price = |amount_in: u64| {
+            let new_reserve_out =
+                RESERVE - constant_product_out(amount_in, RESERVE, RESERVE).unwrap();
+            (RESERVE + amount_in) as f64 / new_reserve_out as f64
+        };
+        // a balanced pool is at 1.0, reaching 4.0 takes doubling the source reserve
+        let target = Decimal::new(4_000_000, 6);
+        let amount_in = amount_in_to_reach_price(RESERVE, RESERVE, target).unwrap();
+        assert!((RESERVE - 1..=RESERVE + 1).contains(&amount_in));
+        assert!(spot_price(amount_in) >= 4.0);
+        assert!(spot_price(amount_in - 1) < 4.0);
+
+        let target = Decimal::new(1_010, 3);
+        let amount_in = amount_in_to_reach_price(RESERVE, RESERVE, target).unwrap();
+        assert!(spot_price(amount_in) >= 1.01);
+        assert!(spot_price(amount_in - 1) < 1.01);
+
+        assert_eq!(
+            amount_in_to_reach_price(RESERVE, RESERVE, Decimal::new(1, 0)),
+            Some(0)
+        );
+        // buying only raises the price
+        assert_eq!(
+            amount_in_to_reach_price(RESERVE, RESERVE, Decimal::new(5, 1)),
+            None
+        );
+        // more than the source reserve can ever hold
+        assert_eq!(
+            amount_in_to_reach_price(RESERVE, RESERVE, Decimal::new(2 * u128::from(u64::MAX), 0)),
+            None
+        );
+        assert_eq!(
+            amount_in_to_reach_price(0, RESERVE, Decimal::new(2, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_split_swap() {
+        let chunks = split_swap(1_000_003, 4);
+        assert_eq!(chunks, vec![250_000, 250_000, 250_000, 250_003]);
+        assert_eq!(chunks.iter().sum::<u64>(), 1_000_003);
+        assert_eq!(split_swap(2, 5), vec![0, 0, 0, 0, 2]);
+        assert_eq!(split_swap(u64::MAX, 7).iter().sum::<u64>(), u64::MAX);
+        assert!(split_swap(1_000, 0).is_empty());
+
+        let fees = fees();
+        let total_in = RESERVE / 10;
+        let single = swap_exact_in(total_in, RESERVE, RESERVE, &fees)
+            .unwrap()
+            .amount_swapped;
+        assert_eq!(
+            split_swap_out(&[total_in], RESERVE, RESERVE, &fees),
+            Some(single)
+        );
+        // the fees of earlier chunks stay in the pool for the later ones
+        let split = split_swap_out(&split_swap(total_in, 10), RESERVE, RESERVE, &fees).unwrap();
+        assert!(split > single && split < single + single / 1_000);
+        assert_eq!(split_swap_out(&[], RESERVE, RESERVE, &fees), Some(0));
+        assert_eq!(
+            split_swap_out(&[1, u64::MAX], RESERVE, RESERVE, &fees),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_tradeable() {
+        assert!(is_tradeable(RESERVE, RESERVE, 1_000));
+        assert!(is_tradeable(1_000, 1_000, 1_000));
+        assert!(!is_tradeable(999, RESERVE, 1_000));
+        assert!(!is_tradeable(RESERVE, 999, 1_000));
+        assert!(!is_tradeable(0, 0, 1_000));
+        // without a minimum only empty reserves are excluded
+        assert!(is_tradeable(1, 1, 0));
+        assert!(!is_tradeable(0, RESERVE, 0));
+    }
+
+    #[test]
+    fn test_is_dust_swap() {
+        let fees = fees();
+        // 1 token in a pool with 1000x more source than destination rounds to nothing
+        assert!(is_dust_swap(1, RESERVE, RESERVE / 1_000, &fees));
+        assert!(is_dust_swap(999, RESERVE, RESERVE / 1_000, &fees));
+        assert!(!is_dust_swap(1_002, RESERVE, RESERVE / 1_000, &fees));
+        assert!(!is_dust_swap(1_000_000, RESERVE, RESERVE, &fees));
+        assert!(is_dust_swap(0, RESERVE, RESERVE, &fees));
+        // failing swaps aren't dust
+        assert!(!is_dust_swap(u64::MAX, RESERVE, RESERVE, &fees));
+    }
+
+    #[test]
+    fn test_apply_swap_to_reserves() {
+        let fees = fees();
+        let amount_in = 1_000_000;
+        let result = swap_exact_in(amount_in, RESERVE, RESERVE, &fees).unwrap();
+        assert_eq!(
+            apply_swap_to_reserves(RESERVE, RESERVE, amount_in, result.amount_swapped),
+            Some((result.new_source_amount, RESERVE - result.amount_swapped))
+        );
+        assert_eq!(
+            apply_swap_to_reserves(RESERVE, RESERVE, 0, RESERVE),
+            Some((RESERVE, 0))
+        );
+
+        // overdrawn or overflowing reserves
+        assert_eq!(
+            apply_swap_to_reserves(RESERVE, RESERVE, amount_in, RESERVE + 1),
+            None
+        );
+        assert_eq!(apply_swap_to_reserves(u64::MAX, RESERVE, 1, 0), None);
+    }
+
+    #[test]
+    fn test_swap_exact_in_partial() {
+        let fees = fees();
+        let amount_in = 1_000_000;
+        let (filled_in, result) =
+            swap_exact_in_partial(amount_in, RESERVE, RESERVE, &fees).unwrap();
+        let full = swap_exact_in(amount_in, RESERVE, RESERVE, &fees).unwrap();
+        assert_eq!(filled_in, amount_in);
+        assert_eq!(result.amount_swapped, full.amount_swapped);
+        assert_eq!(result.new_source_amount, full.new_source_amount);
+
+        // a shallow pool runs out at 999 tokens, more input only raises the price
+        let shallow = 1_000;
+        let amount_in = RESERVE * 1_000;
+        let (filled_in, result) =
+            swap_exact_in_partial(amount_in, RESERVE, shallow, &fees).unwrap();
+        assert!(filled_in < amount_in);
+        assert_eq!(
+            constant_product_out(filled_in, RESERVE, shallow),
+            Some(shallow - 1)
+        );
+        assert!(constant_product_out(filled_in - 1, RESERVE, shallow).unwrap() < shallow - 1);
+        assert_eq!(
+            result.amount_swapped,
+            swap_exact_in(amount_in, RESERVE, shallow, &fees)
+                .unwrap()
+                .amount_swapped
+        );
+        assert_eq!(result.new_source_amount, RESERVE + filled_in);
+
+        // the source reserve can't grow past u64::MAX
+        let deep = u64::MAX - RESERVE;
+        assert!(swap_exact_in(RESERVE * 2, deep, RESERVE, &fees).is_none());
+        let (filled_in, result) = swap_exact_in_partial(RESERVE * 2, deep, RESERVE, &fees).unwrap();
+        assert_eq!(filled_in, RESERVE);
+        assert_eq!(result.new_source_amount, u64::MAX);
+
+        assert!(swap_exact_in_partial(amount_in, 0, RESERVE, &fees).is_none());
+        assert!(swap_exact_in_partial(amount_in, RESERVE, 0, &fees).is_none());
+    }
+
+    #[test]
+    fn test_swap_exact_out() {
+        let pool_fees = Fees {
+            admin_trade_fee_numerator: 1,
+            admin_trade_fee_denominator: 2,
+            trade_fee_numerator: 30,
+            ..fees()
+        };
+        for amount_out in [1, 999, 1_000_000, RESERVE / 2, RESERVE - 10_000_000].iter() {
+            let result = swap_exact_out(*amount_out, RESERVE, RESERVE, &pool_fees).unwrap();
+            let amount_in = result.new_source_amount - RESERVE;
+            assert!(result.amount_swapped >= *amount_out);
+            let exact_in = swap_exact_in(amount_in, RESERVE, RESERVE, &pool_fees).unwrap();
+            assert!(exact_in.amount_swapped >= *amount_out);
+            assert_eq!(exact_in.amount_swapped, result.amount_swapped);
+            assert_eq!(exact_in.fee, result.fee);
+        }
+        // 30 bps on top of the output
+        let result = swap_exact_out(1_000_000, RESERVE, RESERVE, &pool_fees).unwrap();
+        assert!(result.amount_swapped - 1_000_000 <= 1);
+        assert!(result.fee >= 3_009 && result.fee <= 3_010);
+
+        let zero_fees = Fees {
+            trade_fee_numerator: 0,
+            ..fees()
+        };
+        for amount_out in [1, 999, 1_000_000, RESERVE / 2].iter() {
+            let result = swap_exact_out(*amount_out, RESERVE, RESERVE, &zero_fees).unwrap();
+            assert_eq!(
+                Some(result.new_source_amount - RESERVE),
+                constant_product_in(*amount_out, RESERVE, RESERVE)
+            );
+            assert_eq!(result.fee, 0);
+        }
+
+        // more than the pool holds, or a fee of 100%
+        assert!(swap_exact_out(RESERVE, RESERVE, RESERVE, &pool_fees).is_none());
+        assert!(swap_exact_out(RESERVE - 1, RESERVE, RESERVE, &pool_fees).is_none());
+        let all_fees = Fees {
+            trade_fee_numerator: 10_000,
+            ..fees()
+        };
+        assert!(swap_exact_out(1_000, RESERVE, RESERVE, &all_fees).is_none());
+    }
+
+    #[test]
+    fn test_deposit_lp_tokens() {
+        // sqrt(1_000_000 * 4_000_000) = 2_000_000
+        assert_eq!(
+            deposit_lp_tokens(1_000_000, 4_000_000, 0, 0, 0),
+            Some(2_000_000 - MINIMUM_LIQUIDITY)
+        );
+        // too small to cover the locked liquidity
+        assert_eq!(deposit_lp_tokens(1_000, 1_000, 0, 0, 0), None);
+        assert_eq!(deposit_lp_tokens(999, 1_000_000, 0, 0, 0), Some(30_606));
+        assert_eq!(deposit_lp_tokens(10, 10, 0, 0, 0), None);
+        assert_eq!(deposit_lp_tokens(0, RESERVE, 0, 0, 0), None);
+        assert_eq!(
+            deposit_lp_tokens(u64::MAX, u64::MAX, 0, 0, 0),
+            Some(u64::MAX - MINIMUM_LIQUIDITY)
+        );
+
+        // later deposits mint for the smaller share, of the supply including the locked tokens
+        let supply = deposit_lp_tokens(1_000_000, 4_000_000, 0, 0, 0).unwrap() + MINIMUM_LIQUIDITY;
+        assert_eq!(supply, 2_000_000);
+        assert_eq!(
+            deposit_lp_tokens(100, 400, 1_000_000, 4_000_000, supply),
+            Some(200)
+        );
+        assert_eq!(
+            deposit_lp_tokens(100, 4_000_000, 1_000_000, 4_000_000, supply),
+            Some(200)
+        );
+        assert_eq!(
+            deposit_lp_tokens(0, 400, 1_000_000, 4_000_000, supply),
+            None
+        );
+        assert_eq!(deposit_lp_tokens(100, 400, 0, 4_000_000, supply), None);
+    }
+
+    #[test]
+    fn test_first_deposit_full_withdraw() {
+        let (reserve_a, reserve_b) = (1_000_000, 4_000_000);
+        let minted = deposit_lp_tokens(reserve_a, reserve_b, 0, 0, 0).unwrap();
+        let supply = minted + MINIMUM_LIQUIDITY;
+        let fees = Fees {
+            withdraw_fee_numerator: 0,
+            ..fees()
+        };
+        let converter = PoolTokenConverter {
+            supply,
+            token_a: reserve_a,
+            token_b: reserve_b,
+            fees: &fees,
+        };
+        let (out_a, _, _) = converter.token_a_rate(minted).unwrap();
+        let (out_b, _, _) = converter.token_b_rate(minted).unwrap();
+
+        // withdrawing everything minted leaves the locked tokens and what they're worth
+        let supply = supply - minted;
+        assert!(supply >= MINIMUM_LIQUIDITY);
+        assert_eq!((reserve_a - out_a, reserve_b - out_b), (500, 2_000));
+
+        // so the next deposit is priced on that supply, not started from scratch
+        assert_eq!(
+            deposit_lp_tokens(500, 2_000, reserve_a - out_a, reserve_b - out_b, supply),
+            Some(MINIMUM_LIQUIDITY)
+        );
+    }
+
+    #[test]
+    fn test_deposit_lp_tokens_with_slippage() {
+        let supply = 2_000_000;
+        let reserves = [1_000_000, 4_000_000];
+        assert_eq!(
+            deposit_lp_tokens_with_slippage(&[100_000, 400_000], &reserves, supply, 0),
+            Some((200_000, 200_000))
+        );
+        assert_eq!(
+            deposit_lp_tokens_with_slippage(&[100_000, 400_000], &reserves, supply, 50),
+            Some((200_000, 199_000))
+        );
+        for slippage_bps in [1, 30, 100, 9_999, 10_000].iter() {
+            let (expected, min) =
+                deposit_lp_tokens_with_slippage(&[999, 4_001], &reserves, supply, *slippage_bps)
+                    .unwrap();
+            assert_eq!(
+                Some(expected),
+                deposit_lp_tokens(999, 4_001, reserves[0], reserves[1], supply)
+            );
+            assert!(min <= expected);
+        }
+        assert_eq!(
+            deposit_lp_tokens_with_slippage(&[100, 400], &reserves, supply, 10_000),
+            Some((200, 0))
+        );
+
+        assert_eq!(
+            deposit_lp_tokens_with_slippage(&[100, 400], &reserves, supply, 10_001),
+            None
+        );
+        assert_eq!(
+            deposit_lp_tokens_with_slippage(&[100], &reserves, supply, 50),
+            None
+        );
+        assert_eq!(
+            deposit_lp_tokens_with_slippage(&[0, 400], &reserves, supply, 50),
+            None
+        );
+    }
+
+    #[test]
+    fn test_deposit_share_bps() {
+        let supply = 2_000_000;
+        // doubling the pool
+        let minted = deposit_lp_tokens(1_000_000, 4_000_000, 1_000_000, 4_000_000, supply).unwrap();
+        assert_eq!(deposit_share_bps(minted, supply + minted), Some(5_000));
+
+        // a tiny deposit
+        let minted = deposit_lp_tokens(100, 400, 1_000_000, 4_000_000, supply).unwrap();
+        assert_eq!(deposit_share_bps(minted, supply + minted), Some(0));
+        assert_eq!(deposit_share_bps(10_000, 10_000_000), Some(10));
+
+        // the first deposit owns all of the supply but the locked tokens
+        let minted = deposit_lp_tokens(1_000_000, 4_000_000, 0, 0, 0).unwrap();
+        assert_eq!(
+            deposit_share_bps(minted, minted + MINIMUM_LIQUIDITY),
+            Some(9_995)
+        );
+        assert_eq!(deposit_share_bps(u64::MAX, u64::MAX), Some(10_000));
+
+        assert_eq!(deposit_share_bps(1, 0), None);
+        assert_eq!(deposit_share_bps(0, 0), None);
+    }
+
+    #[test]
+    fn test_quote_two_hop() {
+        let fees = fees();
+        let amount_in = 1_000_000;
+        let hop = swap_exact_in(amount_in, RESERVE, RESERVE, &fees).unwrap();
+        let expected = swap_exact_in(hop.amount_swapped, RESERVE, RESERVE, &fees)
+            .unwrap()
+            .amount_swapped;
+        let quote = quote_two_hop(
+            amount_in,
+            (RESERVE, RESERVE, &fees),
+            (RESERVE, RESERVE, &fees),
+        );
+        assert_eq!(quote, Some(expected));
+        // two rounds of slippage and fees
+        assert!(expected < hop.amount_swapped);
+
+        // the second pool can't absorb the first pool's output
+        assert_eq!(
+            quote_two_hop(amount_in, (RESERVE, RESERVE, &fees), (RESERVE, 0, &fees)),
+            None
+        );
+        assert_eq!(
+            quote_two_hop(amount_in, (RESERVE, RESERVE, &fees), (u64::MAX, 1, &fees)),
+            None
+        );
+        assert_eq!(
+            quote_two_hop(amount_in, (0, RESERVE, &fees), (RESERVE, RESERVE, &fees)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_min_out_multi_hop() {
+        let expected_out = 1_000_000;
+        // a single hop is plain slippage on the output
+        assert_eq!(
+            min_out_multi_hop(expected_out, 1, 50),
+            percent_of(expected_out, 9_950, 10_000)
+        );
+        assert_eq!(min_out_multi_hop(expected_out, 1, 50), Some(995_000));
+        // two hops compound to 99.5% of 99.5%, not 99%
+        assert_eq!(min_out_multi_hop(expected_out, 2, 50), Some(990_025));
+        assert_eq!(min_out_multi_hop(expected_out, 3, 50), Some(985_074));
+
+        let fees = fees();
+        let quote = quote_two_hop(
+            1_000_000,
+            (RESERVE, RESERVE, &fees),
+            (RESERVE, RESERVE, &fees),
+        )
+        .unwrap();
+        assert!(min_out_multi_hop(quote, 2, 50).unwrap() < quote);
+
+        assert_eq!(min_out_multi_hop(expected_out, 0, 50), Some(expected_out));
+        assert_eq!(min_out_multi_hop(expected_out, 2, 0), Some(expected_out));
+        assert_eq!(min_out_multi_hop(expected_out, 2, 10_000), Some(0));
+        assert_eq!(min_out_multi_hop(expected_out, 2, 10_001), None);
+        assert!(min_out_multi_hop(u64::MAX, 2, 50).is_some());
+    }
+    #[test]
+    fn test_pool_state() {
+        // 1000 USDC (6 decimals) against 10 SOL (9 decimals)
+        let pool = PoolState {
+            reserve_a: 1_000_000_000,
+            reserve_b: 10_000_000_000,
+            fees: fees(),
+            decimals_a: 6,
+            decimals_b: 9,
+        };
+        // 10 USDC buys a little under 0.1 SOL
+        let a_to_b = pool.quote_a_to_b(10_000_000).unwrap();
+        assert_eq!(
+            a_to_b.amount_swapped,
+            swap_exact_in(10_000_000, pool.reserve_a, pool.reserve_b, &pool.fees)
+                .unwrap()
+                .amount_swapped
+        );
+        assert!(a_to_b.amount_swapped > 98_000_000 && a_to_b.amount_swapped < 100_000_000);
+        assert_eq!(a_to_b.new_source_amount, pool.reserve_a + 10_000_000);
+
+        // 0.1 SOL buys a little under 10 USDC
+        let b_to_a = pool.quote_b_to_a(100_000_000).unwrap();
+        assert!(b_to_a.amount_swapped > 9_800_000 && b_to_a.amount_swapped < 10_000_000);
+        assert_eq!(b_to_a.new_source_amount, pool.reserve_b + 100_000_000);
+        assert!(b_to_a.new_destination_amount < pool.reserve_a);
+
+        // the decimals don't change the quotes
+        let rescaled = PoolState {
+            decimals_a: 9,
+            decimals_b: 6,
+            ..pool
+        };
+        assert_eq!(
+            rescaled.quote_a_to_b(10_000_000).unwrap().amount_swapped,
+            a_to_b.amount_swapped
+        );
+        assert_eq!(
+            rescaled.quote_b_to_a(100_000_000).unwrap().amount_swapped,
+            b_to_a.amount_swapped
+        );
+        let large = PoolState {
+            reserve_a: u64::MAX / 2,
+            ..pool
+        };
+        assert!(large.quote_a_to_b(10_000_000).is_some());
+    }
+
+    #[test]
+    fn test_best_quote() {
+        let pool = PoolState {
+            reserve_a: RESERVE,
+            reserve_b: RESERVE,
+            fees: fees(),
+            decimals_a: 6,
+            decimals_b: 6,
+        };
+        let deep = PoolState {
+            reserve_a: 10 * RESERVE,
+            reserve_b: 10 * RESERVE,
+            ..pool
+        };
+        let expensive = PoolState {
+            fees: Fees::from_bps(100, 0, 0, 0),
+            ..deep
+        };
+        let empty = PoolState {
+            reserve_b: 0,
+            ..pool
+        };
+
+        let amount_in = RESERVE / 10;
+        let (index, result) = best_quote(amount_in, &[pool, expensive, deep, empty]).unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(
+            result.amount_swapped,
+            deep.quote_a_to_b(amount_in).unwrap().amount_swapped
+        );
+        // the cheaper of two equally deep pools, and the first of two identical ones
+        assert_eq!(best_quote(amount_in, &[expensive, deep]).unwrap().0, 1);
+        assert_eq!(best_quote(amount_in, &[pool, pool]).unwrap().0, 0);
+
+        assert!(best_quote(amount_in, &[]).is_none());
+        assert!(best_quote(u64::MAX, &[pool, deep]).is_none());
+    }
+
+    #[test]
+    fn test_effective_price() {
+        let fees = fees();
+        // spot price of a balanced pool is 1.0
+        let small = effective_price(1_000_000, RESERVE, RESERVE, &fees, 6).unwrap();
+        assert!(small.value >= 1_000_000 && small.value < 1_003_000);
+        assert_eq!(small.to_string(), "1.001100");
+
+        let large = effective_price(RESERVE / 2, RESERVE, RESERVE, &fees, 6).unwrap();
+        assert!(large.value > 1_400_000);
+
+        assert_eq!(effective_price(1, RESERVE, 0, &fees, 6), None);
+    }
+
+    #[test]
+    fn test_spot_price_after() {
+        let spot = spot_price(RESERVE, RESERVE, 6).unwrap();
+        assert_eq!(spot, Decimal::new(1_000_000, 6));
+        assert_eq!(spot_price_after(0, RESERVE, RESERVE, 6), Some(spot));
+
+        // buying moves the price against the buyer, more so for larger trades
+        let small = spot_price_after(RESERVE / 100, RESERVE, RESERVE, 6).unwrap();
+        let large = spot_price_after(RESERVE / 10, RESERVE, RESERVE, 6).unwrap();
+        assert!(small.value > spot.value);
+        assert!(large.value > small.value);
+        // (x + dx)^2 / x^2 for x * y = k, less the output's rounding
+        assert!((1_020_099..=1_020_100).contains(&small.value));
+        assert!((1_209_999..=1_210_000).contains(&large.value));
+        // the price paid lies between the spot prices before and after
+        let paid = effective_price(RESERVE / 10, RESERVE, RESERVE, &fees(), 6).unwrap();
+        assert!(spot.value < paid.value && paid.value < large.value);
+
+        assert_eq!(spot_price(RESERVE, 0, 6), None);
+        assert_eq!(spot_price_after(1, RESERVE, 0, 6), None);
+        assert_eq!(spot_price_after(u64::MAX, RESERVE, RESERVE, 6), None);
+    }
+
+    #[test]
+    fn test_marginal_prices() {
+        let one = Decimal::new(1_000_000, 6);
+        assert_eq!(marginal_prices(RESERVE, RESERVE, 6), Some((one, one)));
+
+        // 1 A is worth 40 B
+        let (a_per_b, b_per_a) = marginal_prices(RESERVE, 40 * RESERVE, 6).unwrap();
+        assert_eq!(a_per_b, Decimal::new(25_000, 6));
+        assert_eq!(b_per_a, Decimal::new(40_000_000, 6));
+        assert_eq!(a_per_b.value * b_per_a.value, one.value * one.value);
+
+        // reciprocals up to rounding
+        let (a_per_b, b_per_a) = marginal_prices(3 * RESERVE, 7 * RESERVE, 9).unwrap();
+        let product = a_per_b.value * b_per_a.value;
+        let exact = 10u128.pow(18);
+        assert!(product <= exact && exact - product < 10u128.pow(10));
+
+        assert_eq!(marginal_prices(RESERVE, 0, 6), None);
+        assert_eq!(marginal_prices(0, RESERVE, 6), None);
+    }
+
+    proptest! {
+        #[test]
+        fn test_constant_product_out_matches_reference(
+            amount_in in prop_oneof![0..=RESERVE, any::<u64>()],
+            reserve_in in prop_oneof![0..=RESERVE, any::<u64>()],
+            reserve_out in prop_oneof![0..=RESERVE, any::<u64>()],
+        ) {
+            let optimized = constant_product_out(amount_in, reserve_in, reserve_out);
+            let reference = reference_constant_product_out(amount_in, reserve_in, reserve_out);
+            prop_assert_eq!(optimized.is_some(), reference.is_some());
+            if let (Some(optimized), Some(reference)) = (optimized, reference) {
+                prop_assert_eq!(optimized, reference);
+            }
+        }
+    }
+}
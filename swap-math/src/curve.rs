@@ -1,9 +1,11 @@
 //! Swap calculations and curve invariant implementation
 
-use num_traits::ToPrimitive;
 use swap_client::fees::Fees;
 
-use crate::{bn::U192, math::FeeCalculator};
+use crate::{
+    bn::U192,
+    math::{sum_checked, FeeCalculator},
+};
 
 /// Number of coins
 const N_COINS: u8 = 2;
@@ -46,6 +48,65 @@ pub struct StableSwap {
     stop_ramp_ts: i64,
 }
 
+/// Linearly interpolates the amplification coefficient (A) ramping from `amp_start` at
+/// `t_start` to `amp_end` at `t_end`, clamped to `amp_start` before the ramp and to
+/// `amp_end` after it, like Curve's `_A()`.
+pub fn ramped_amp(amp_start: u64, amp_end: u64, t_start: i64, t_end: i64, now: i64) -> u64 {
+    if now >= t_end {
+        return amp_end;
+    }
+    if now <= t_start {
+        return amp_start;
+    }
+    // t_start < now < t_end, so both spans are positive and fit in u128
+    let time_delta = (now as i128 - t_start as i128) as u128;
+    let time_range = (t_end as i128 - t_start as i128) as u128;
+    // the deltas are at most the amp range, so they fit back in a u64
+    if amp_end >= amp_start {
+        // Ramp up
+        let amp_delta = (amp_end - amp_start) as u128 * time_delta / time_range;
+        amp_start + amp_delta as u64
+    } else {
+        // Ramp down
+        let amp_delta = (amp_start - amp_end) as u128 * time_delta / time_range;
+        amp_start - amp_delta as u64
+    }
+}
+
+/// Checks whether a deposit is in the pool's proportions, i.e. each token's share of its
+/// reserve differs from the first token's share by at most `tolerance_bps` of it.
+/// Such a deposit doesn't move the price, so no normalized trade fee is owed.
+/// Returns `false` for mismatched lengths and empty reserves.
+pub fn is_balanced_deposit(deposit_amounts: &[u64], reserves: &[u64], tolerance_bps: u64) -> bool {
+    if deposit_amounts.len() != reserves.len() || reserves.contains(&0) {
+        return false;
+    }
+    let (deposit_0, reserve_0) = match (deposit_amounts.first(), reserves.first()) {
+        (Some(deposit), Some(reserve)) => (U192::from(*deposit), U192::from(*reserve)),
+        _ => return false,
+    };
+    deposit_amounts
+        .iter()
+        .zip(reserves.iter())
+        .all(|(deposit, reserve)| {
+            // |d_i / r_i - d_0 / r_0| <= tolerance * d_0 / r_0, multiplied by r_i * r_0
+            let within = || -> Option<bool> {
+                let share = U192::from(*deposit).checked_mul(reserve_0)?;
+                let reference = deposit_0.checked_mul((*reserve).into())?;
+                let deviation = if share > reference {
+                    share.checked_sub(reference)?
+                } else {
+                    reference.checked_sub(share)?
+                };
+                Some(
+                    deviation.checked_mul(10_000.into())?
+                        <= reference.checked_mul(tolerance_bps.into())?,
+                )
+            };
+            within().unwrap_or(false)
+        })
+}
+
 impl StableSwap {
     /// New StableSwap calculator
     pub fn new(
@@ -85,45 +146,23 @@ impl StableSwap {
         numerator.checked_div(denominator)
     }
 
-    /// Compute the amplification coefficient (A)
+    /// Compute the amplification coefficient (A), ramping from the initial to the target
+    /// amplification coefficient, see [ramped_amp]. Always `Some`.
     pub fn compute_amp_factor(&self) -> Option<u64> {
-        if self.current_ts < self.stop_ramp_ts {
-            let time_range = self.stop_ramp_ts.checked_sub(self.start_ramp_ts)?;
-            let time_delta = self.current_ts.checked_sub(self.start_ramp_ts)?;
-
-            // Compute amp factor based on ramp time
-            if self.target_amp_factor >= self.initial_amp_factor {
-                // Ramp up
-                let amp_range = self
-                    .target_amp_factor
-                    .checked_sub(self.initial_amp_factor)?;
-                let amp_delta = (amp_range as u128)
-                    .checked_mul(time_delta.to_u128()?)?
-                    .checked_div(time_range.to_u128()?)?
-                    .to_u64()?;
-                self.initial_amp_factor.checked_add(amp_delta)
-            } else {
-                // Ramp down
-                let amp_range = self
-                    .initial_amp_factor
-                    .checked_sub(self.target_amp_factor)?;
-                let amp_delta = (amp_range as u128)
-                    .checked_mul(time_delta.to_u128()?)?
-                    .checked_div(time_range.to_u128()?)?
-                    .to_u64()?;
-                self.initial_amp_factor.checked_sub(amp_delta)
-            }
-        } else {
-            // when stop_ramp_ts == 0 or current_ts >= stop_ramp_ts
-            Some(self.target_amp_factor)
-        }
+        Some(ramped_amp(
+            self.initial_amp_factor,
+            self.target_amp_factor,
+            self.start_ramp_ts,
+            self.stop_ramp_ts,
+            self.current_ts,
+        ))
     }
 
     /// Compute stable swap invariant (D)
     /// Equation:
     /// A * sum(x_i) * n**n + D = A * D * n**n + D**(n+1) / (n**n * prod(x_i))
     pub fn compute_d(&self, amount_a: u64, amount_b: u64) -> Option<U192> {
-        let sum_x = amount_a.checked_add(amount_b)?; // sum(x_i), a.k.a S
+        let sum_x = sum_checked(&[amount_a, amount_b])?; // sum(x_i), a.k.a S
         if sum_x == 0 {
             Some(0.into())
         } else {
@@ -158,6 +197,18 @@ impl StableSwap {
         }
     }
 
+    /// Compute stable swap invariant (D) over a slice of reserves.
+    /// A single-coin pool degenerates to `D = x_0`, which solves the invariant for n = 1
+    /// (`A * x + D = A * D + D**2 / x`) without running Newton's method.
+    /// Two coins use [StableSwap::compute_d]; other lengths aren't supported and return `None`.
+    pub fn compute_d_for_amounts(&self, amounts: &[u64]) -> Option<U192> {
+        match *amounts {
+            [amount] => Some(amount.into()),
+            [amount_a, amount_b] => self.compute_d(amount_a, amount_b),
+            _ => None,
+        }
+    }
+
     /// Compute the amount of pool tokens to mint after a deposit
     pub fn compute_mint_amount_for_deposit(
         &self,
@@ -179,6 +230,12 @@ impl StableSwap {
         let d_1 = self.compute_d(new_balances[0], new_balances[1])?;
         if d_1 <= d_0 {
             None
+        } else if is_balanced_deposit(&[deposit_amount_a, deposit_amount_b], &old_balances, 0) {
+            // A proportional deposit doesn't move the price, so there is no fee to charge
+            U192::from(pool_token_supply)
+                .checked_mul(d_1.checked_sub(d_0)?)?
+                .checked_div(d_0)?
+                .to_u64()
         } else {
             // Recalculate the invariant accounting for fees
             for i in 0..new_balances.len() {
@@ -326,3 +383,130 @@ impl StableSwap {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_balanced_deposit() {
+        let reserves = [1_000_000, 2_000_000];
+        assert!(is_balanced_deposit(&[1_000, 2_000], &reserves, 0));
+        // 0.5% more of the second token
+        assert!(!is_balanced_deposit(&[1_000, 2_010], &reserves, 0));
+        assert!(!is_balanced_deposit(&[1_000, 2_010], &reserves, 49));
+        assert!(is_balanced_deposit(&[1_000, 2_010], &reserves, 50));
+        assert!(is_balanced_deposit(&[1_000, 1_990], &reserves, 50));
+        // clearly one sided
+        assert!(!is_balanced_deposit(&[1_000, 0], &reserves, 50));
+        assert!(!is_balanced_deposit(&[0, 2_000], &reserves, 50));
+
+        assert!(is_balanced_deposit(
+            &[u64::MAX, u64::MAX],
+            &[u64::MAX, u64::MAX],
+            0
+        ));
+        assert!(!is_balanced_deposit(
+            &[1_000, 2_000],
+            &[0, 2_000_000],
+            10_000
+        ));
+        assert!(!is_balanced_deposit(&[1_000], &reserves, 10_000));
+        assert!(!is_balanced_deposit(&[], &[], 10_000));
+    }
+
+    #[test]
+    fn test_balanced_deposit_fee() {
+        let fees = Fees {
+            admin_trade_fee_numerator: 0,
+            admin_trade_fee_denominator: 1,
+            admin_withdraw_fee_numerator: 0,
+            admin_withdraw_fee_denominator: 1,
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            withdraw_fee_numerator: 0,
+            withdraw_fee_denominator: 1,
+        };
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let reserve = 1_000_000_000;
+        let supply = 2_000_000_000;
+
+        // a proportional deposit of 10% mints 10% of the supply
+        let minted = swap
+            .compute_mint_amount_for_deposit(
+                reserve / 10,
+                reserve / 10,
+                reserve,
+                reserve,
+                supply,
+                &fees,
+            )
+            .unwrap();
+        assert_eq!(minted, supply / 10);
+
+        // the same value deposited one sided pays the normalized fee
+        let one_sided = swap
+            .compute_mint_amount_for_deposit(reserve / 5, 0, reserve, reserve, supply, &fees)
+            .unwrap();
+        assert!(one_sided < minted);
+    }
+
+    #[test]
+    fn test_compute_d_for_amounts() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        assert_eq!(swap.compute_d_for_amounts(&[0]), Some(0.into()));
+        assert_eq!(
+            swap.compute_d_for_amounts(&[u64::MAX]),
+            Some(u64::MAX.into())
+        );
+
+        // D = x solves A * x + D = A * D + D**2 / x for a single coin
+        let amount = 1_234_567_u64;
+        let amp = U192::from(swap.compute_amp_factor().unwrap());
+        let x = U192::from(amount);
+        let d = swap.compute_d_for_amounts(&[amount]).unwrap();
+        assert_eq!(amp * x + d, amp * d + d * d / x);
+
+        assert_eq!(
+            swap.compute_d_for_amounts(&[1_000, 2_000]),
+            swap.compute_d(1_000, 2_000)
+        );
+        assert_eq!(swap.compute_d_for_amounts(&[]), None);
+        assert_eq!(swap.compute_d_for_amounts(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn test_ramped_amp() {
+        let t_start = 1_000;
+        let t_end = t_start + MIN_RAMP_DURATION;
+        // clamped before and after the ramp
+        assert_eq!(ramped_amp(100, 200, t_start, t_end, 0), 100);
+        assert_eq!(ramped_amp(100, 200, t_start, t_end, t_start), 100);
+        assert_eq!(ramped_amp(100, 200, t_start, t_end, t_end), 200);
+        assert_eq!(ramped_amp(100, 200, t_start, t_end, i64::MAX), 200);
+
+        // mid ramp, up and down
+        let mid = t_start + MIN_RAMP_DURATION / 2;
+        assert_eq!(ramped_amp(100, 200, t_start, t_end, mid), 150);
+        assert_eq!(ramped_amp(200, 100, t_start, t_end, mid), 150);
+        assert_eq!(
+            ramped_amp(100, 200, t_start, t_end, t_start + MIN_RAMP_DURATION / 4),
+            125
+        );
+        assert_eq!(ramped_amp(MIN_AMP, MAX_AMP, i64::MIN, i64::MAX, 0), 500_000);
+        assert_eq!(
+            ramped_amp(0, u64::MAX, i64::MIN, i64::MAX, 0),
+            u64::MAX / 2 + 1
+        );
+        assert_eq!(ramped_amp(u64::MAX, 0, i64::MIN, i64::MAX, 0), u64::MAX / 2);
+
+        // the calculator delegates to it, before, inside and after the ramp
+        for now in [0, t_start, mid, t_end, i64::MAX] {
+            let swap = StableSwap::new(100, 200, now, t_start, t_end);
+            assert_eq!(
+                swap.compute_amp_factor(),
+                Some(ramped_amp(100, 200, t_start, t_end, now))
+            );
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Fixed-point decimal numbers
+
+use core::convert::TryFrom;
+use num_traits::ToPrimitive;
+
+/// A number represented as `value / 10^decimals`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Decimal {
+    /// Scaled integer value
+    pub value: u128,
+    /// Number of decimal places in `value`
+    pub decimals: u32,
+}
+
+impl Decimal {
+    /// Creates a new [Decimal].
+    pub fn new(value: u128, decimals: u32) -> Self {
+        Decimal { value, decimals }
+    }
+
+    /// Splits the value into its integer part and its fractional part,
+    /// the latter still scaled by `10^decimals`. Does not allocate.
+    pub fn to_parts(&self) -> (u128, u128) {
+        match 10u128.checked_pow(self.decimals) {
+            Some(scale) => (self.value / scale, self.value % scale),
+            // every u128 is smaller than the scale
+            None => (0, self.value),
+        }
+    }
+
+    /// Rescales the value to `target_decimals`, rounding down.
+    /// Returns `None` if the result does not fit in a u64.
+    pub fn to_scaled_u64(&self, target_decimals: u32) -> Option<u64> {
+        if target_decimals >= self.decimals {
+            let factor = 10u128.checked_pow(target_decimals.checked_sub(self.decimals)?)?;
+            self.value.checked_mul(factor)?.to_u64()
+        } else {
+            // a factor too large for u128 exceeds any value, so the result is 0
+            match 10u128.checked_pow(self.decimals.checked_sub(target_decimals)?) {
+                Some(factor) => self.value.checked_div(factor)?.to_u64(),
+                None => Some(0),
+            }
+        }
+    }
+
+    /// Approximates the value as a float, for display and analytics only.
+    /// Lossy: f64 holds about 15 significant digits, so large values and long
+    /// fractions are rounded. Never use the result in on-chain math.
+    #[cfg(feature = "std")]
+    pub fn to_f64_lossy(&self) -> f64 {
+        self.value as f64 / 10f64.powi(self.decimals as i32)
+    }
+}
+
+impl core::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (integer, fraction) = self.to_parts();
+        if self.decimals == 0 {
+            write!(f, "{}.", integer)
+        } else {
+            write!(
+                f,
+                "{}.{:0width$}",
+                integer,
+                fraction,
+                width = self.decimals as usize
+            )
+        }
+    }
+}
+
+/// A signed number represented as `value / 10^decimals`, for prices that can be
+/// negative, such as spreads. See [Decimal] for the unsigned counterpart.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SignedDecimal {
+    /// Scaled integer value
+    pub value: i128,
+    /// Number of decimal places in `value`
+    pub decimals: u32,
+}
+
+impl SignedDecimal {
+    /// Creates a new [SignedDecimal].
+    pub fn new(value: i128, decimals: u32) -> Self {
+        SignedDecimal { value, decimals }
+    }
+
+    /// Returns `true` if the value is below zero.
+    pub fn is_negative(&self) -> bool {
+        self.value < 0
+    }
+
+    /// The absolute value, as a [Decimal] with the same decimals.
+    pub fn magnitude(&self) -> Decimal {
+        Decimal::new(self.value.unsigned_abs(), self.decimals)
+    }
+
+    /// Converts to a [Decimal], or `None` if the value is negative.
+    pub fn to_unsigned(&self) -> Option<Decimal> {
+        if self.is_negative() {
+            None
+        } else {
+            Some(self.magnitude())
+        }
+    }
+}
+
+impl From<Decimal> for SignedDecimal {
+    /// Saturates at `i128::MAX` for values above it.
+    fn from(decimal: Decimal) -> Self {
+        let value = i128::try_from(decimal.value).unwrap_or(i128::MAX);
+        SignedDecimal::new(value, decimal.decimals)
+    }
+}
+
+impl PartialOrd for SignedDecimal {
+    /// Only values with the same decimals are ordered, others return `None`.
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        if self.decimals != other.decimals {
+            return None;
+        }
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl core::fmt::Display for SignedDecimal {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.is_negative() {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", self.magnitude())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Decimal::new(1_500_000_000, 9).to_string(), "1.500000000");
+        assert_eq!(Decimal::new(123, 6).to_string(), "0.000123");
+        assert_eq!(Decimal::new(0, 2).to_string(), "0.00");
+        assert_eq!(Decimal::new(42, 0).to_string(), "42.");
+        assert_eq!(
+            Decimal::new(7, 40).to_string(),
+            format!("0.{}7", "0".repeat(39))
+        );
+        assert_eq!(Decimal::new(1_500_000_000, 9).to_parts(), (1, 500_000_000));
+    }
+
+    #[test]
+    fn test_to_scaled_u64() {
+        let price = Decimal::new(1_500_000, 6);
+        assert_eq!(price.to_scaled_u64(6), Some(1_500_000));
+        assert_eq!(price.to_scaled_u64(9), Some(1_500_000_000));
+        assert_eq!(Decimal::new(1_234_567, 6).to_scaled_u64(2), Some(123));
+        assert_eq!(Decimal::new(999, 6).to_scaled_u64(0), Some(0));
+        assert_eq!(Decimal::new(u128::MAX, 60).to_scaled_u64(0), Some(0));
+
+        assert_eq!(Decimal::new(u64::MAX.into(), 0).to_scaled_u64(1), None);
+        assert_eq!(Decimal::new(1, 0).to_scaled_u64(40), None);
+        assert_eq!(Decimal::new(u128::MAX, 0).to_scaled_u64(0), None);
+    }
+
+    #[test]
+    fn test_to_f64_lossy() {
+        assert!((Decimal::new(1_500_000_000, 9).to_f64_lossy() - 1.5).abs() < f64::EPSILON);
+        assert!((Decimal::new(123, 6).to_f64_lossy() - 0.000_123).abs() < f64::EPSILON);
+        assert_eq!(Decimal::new(42, 0).to_f64_lossy(), 42.0);
+        assert_eq!(Decimal::new(0, 9).to_f64_lossy(), 0.0);
+
+        // past 2^53 the integer digits are rounded
+        let large = Decimal::new(u64::MAX as u128 + 1, 0).to_f64_lossy();
+        assert_eq!(large, Decimal::new(u64::MAX.into(), 0).to_f64_lossy());
+        let relative =
+            (Decimal::new(u128::MAX, 18).to_f64_lossy() - 3.402_823_669e20).abs() / 3.4e20;
+        assert!(relative < 1e-9);
+    }
+
+    #[test]
+    fn test_signed_decimal() {
+        let spread = SignedDecimal::new(-1_500_000_000, 9);
+        assert_eq!(spread.to_string(), "-1.500000000");
+        assert_eq!(SignedDecimal::new(-123, 6).to_string(), "-0.000123");
+        assert_eq!(SignedDecimal::new(123, 6).to_string(), "0.000123");
+        assert_eq!(SignedDecimal::new(0, 2).to_string(), "0.00");
+        assert_eq!(
+            SignedDecimal::new(i128::MIN, 0).to_string(),
+            format!("-{}.", i128::MIN.unsigned_abs())
+        );
+
+        let price = SignedDecimal::from(Decimal::new(1_500_000_000, 9));
+        assert!(spread.is_negative());
+        assert!(spread < price);
+        assert!(price > spread);
+        assert!(SignedDecimal::new(-2, 9) < SignedDecimal::new(-1, 9));
+        assert_eq!(spread.partial_cmp(&SignedDecimal::new(1, 6)), None);
+
+        assert_eq!(spread.magnitude(), Decimal::new(1_500_000_000, 9));
+        assert_eq!(spread.to_unsigned(), None);
+        assert_eq!(price.to_unsigned(), Some(Decimal::new(1_500_000_000, 9)));
+        assert_eq!(
+            SignedDecimal::from(Decimal::new(u128::MAX, 0)).value,
+            i128::MAX
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! Error types
+
+#[cfg(feature = "std")]
+use thiserror::Error;
+
+/// Errors that may be returned by the checked math functions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "std", derive(Error))]
+pub enum MathError {
+    /// An intermediate or final value does not fit in its type.
+    #[cfg_attr(feature = "std", error("Arithmetic overflow"))]
+    Overflow,
+    /// The divisor was zero.
+    #[cfg_attr(feature = "std", error("Division by zero"))]
+    DivideByZero,
+    /// One of the pool reserves is zero.
+    #[cfg_attr(feature = "std", error("Pool reserve is empty"))]
+    EmptyReserve,
+    /// The pool cannot provide the requested amount.
+    #[cfg_attr(feature = "std", error("Insufficient liquidity in the pool"))]
+    InsufficientLiquidity,
+}
@@ -1,10 +1,18 @@
 //! Math utilities for stable-swap.
+//!
+//! The `std` feature is enabled by default. Building with `--no-default-features`
+//! compiles the math core as `no_std`, without Borsh serialization of the big number types.
+#![cfg_attr(not(feature = "std"), no_std)]
 #![deny(rustdoc::all)]
 #![allow(rustdoc::missing_doc_code_examples)]
 #![deny(missing_docs)]
 
 pub mod bn;
+pub mod constant_product;
 pub mod curve;
+pub mod decimal;
+pub mod error;
 pub mod math;
 pub mod pool_converter;
 pub mod price;
+pub mod weighted;
@@ -1,58 +1,308 @@
 //! Math helpers
 
 use num_traits::ToPrimitive;
-use swap_client::fees::Fees;
+use swap_client::fees::{Fees, BPS_DENOMINATOR};
 
-const MAX: u64 = 1 << 32;
-const MAX_BIG: u64 = 1 << 48;
-const MAX_SMALL: u64 = 1 << 16;
+use crate::{bn::U256, error::MathError};
+
+/// [mul_div] falls back to 128 bit math once either operand reaches this,
+/// as `(2^32 - 1)^2` is the largest product guaranteed to fit in 64 bits.
+pub const MAX: u64 = 1 << 32;
+/// [mul_div_imbalanced] falls back to 128 bit math once the larger operand reaches this.
+pub const MAX_BIG: u64 = 1 << 48;
+/// [mul_div_imbalanced] falls back to 128 bit math once the smaller operand reaches this.
+/// `MAX_BIG * MAX_SMALL` is exactly `2^64`.
+pub const MAX_SMALL: u64 = 1 << 16;
+
+/// Whether [mul_div] computes `a * b` in 128 bits rather than 64.
+/// Both paths return the same result, the 128 bit one costs more compute.
+#[inline(always)]
+pub fn mul_div_uses_wide_path(a: u64, b: u64) -> bool {
+    a >= MAX || b >= MAX
+}
+
+/// Whether [mul_div_imbalanced] computes `a * b` in 128 bits rather than 64.
+/// Both paths return the same result, the 128 bit one costs more compute.
+#[inline(always)]
+pub fn mul_div_imbalanced_uses_wide_path(a: u64, b: u64) -> bool {
+    a >= MAX_BIG || b >= MAX_SMALL
+}
 
 /// Multiplies two u64s then divides by the third number.
 /// This function attempts to use 64 bit math if possible.
+///
+/// The 64 bit path is only taken when `a < 2^32` and `b < 2^32`,
+/// so `a * b < 2^64` and the product never overflows.
 #[inline(always)]
 pub fn mul_div(a: u64, b: u64, c: u64) -> Option<u64> {
-    if a > MAX || b > MAX {
+    if mul_div_uses_wide_path(a, b) {
         (a as u128)
             .checked_mul(b as u128)?
             .checked_div(c as u128)?
             .to_u64()
     } else {
-        a.checked_mul(b)?.checked_div(c)
+        let product = a.checked_mul(b);
+        debug_assert!(product.is_some(), "mul_div 64 bit path overflowed");
+        product?.checked_div(c)
+    }
+}
+
+/// Multiplies two u64s then divides by the third number,
+/// returning why the calculation failed.
+pub fn mul_div_checked(a: u64, b: u64, c: u64) -> Result<u64, MathError> {
+    if c == 0 {
+        return Err(MathError::DivideByZero);
     }
+    mul_div(a, b, c).ok_or(MathError::Overflow)
 }
 
 /// Multiplies two u64s then divides by the third number.
 /// This assumes that a > b.
+///
+/// The 64 bit path is only taken when `a < 2^48` and `b < 2^16`,
+/// so `a * b < 2^64` and the product never overflows.
 #[inline(always)]
 pub fn mul_div_imbalanced(a: u64, b: u64, c: u64) -> Option<u64> {
-    if a > MAX_BIG || b > MAX_SMALL {
+    if mul_div_imbalanced_uses_wide_path(a, b) {
         (a as u128)
             .checked_mul(b as u128)?
             .checked_div(c as u128)?
             .to_u64()
     } else {
-        a.checked_mul(b)?.checked_div(c)
+        let product = a.checked_mul(b);
+        debug_assert!(
+            product.is_some(),
+            "mul_div_imbalanced 64 bit path overflowed"
+        );
+        product?.checked_div(c)
     }
 }
 
+/// Computes `amount * numerator / denominator`, rounded down, i.e. the `numerator /
+/// denominator` fraction of `amount`. Fractions above 1 return more than `amount`.
+/// Returns `None` if `denominator` is 0 or the result doesn't fit in a u64.
+///
+/// Uses [mul_div_imbalanced], which takes the 64 bit path for the usual
+/// `amount` much larger than `numerator`.
+#[inline(always)]
+pub fn percent_of(amount: u64, numerator: u64, denominator: u64) -> Option<u64> {
+    mul_div_imbalanced(amount, numerator, denominator)
+}
+
+/// Sums `amounts`, returning `None` instead of wrapping if the total overflows a u64.
+pub fn sum_checked(amounts: &[u64]) -> Option<u64> {
+    amounts
+        .iter()
+        .try_fold(0u64, |sum, amount| sum.checked_add(*amount))
+}
+
+/// Maximum number of series terms evaluated for a fractional power.
+const MAX_POW_ITERATIONS: u128 = 256;
+
+/// Computes `a * b / c` in 256 bits, rounded up if `round_up`.
+fn mul_div_u128(a: u128, b: u128, c: u128, round_up: bool) -> Option<u128> {
+    let c = U256::from(c);
+    let product = U256::from(a).checked_mul(U256::from(b))?;
+    let quotient = product.checked_div(c)?;
+    if round_up && quotient.checked_mul(c)? != product {
+        quotient.checked_add(U256::one())?.to_u128()
+    } else {
+        quotient.to_u128()
+    }
+}
+
+/// Computes `(base / scale)^(exp_num / exp_den)` for `scale / 2 <= base <= scale`
+/// and `exp_num < exp_den`, rounded up. With `x = 1 - base / scale` every term of
+/// `(1 - x)^a = 1 - a*x - a*(1-a)/2!*x^2 - ...` past the first is negative, so the
+/// truncated, rounded down terms leave an upper bound, and they at least halve each time.
+fn pow_fraction_up(base: u128, exp_num: u64, exp_den: u64, scale: u128) -> Option<u128> {
+    let x = scale.checked_sub(base)?;
+    let exp_num = u128::from(exp_num);
+    let exp_den = u128::from(exp_den);
+    let mut term = mul_div_u128(exp_num, x, exp_den, false)?;
+    let mut sum = scale.checked_sub(term)?;
+    for k in 2..=MAX_POW_ITERATIONS {
+        if term == 0 {
+            break;
+        }
+        // term * x / scale * (k - 1 - a) / k
+        let numerator = k
+            .checked_sub(1)?
+            .checked_mul(exp_den)?
+            .checked_sub(exp_num)?;
+        let denominator = scale.checked_mul(k)?.checked_mul(exp_den)?;
+        term = U256::from(term)
+            .checked_mul(U256::from(x))?
+            .checked_mul(U256::from(numerator))?
+            .checked_div(U256::from(denominator))?
+            .to_u128()?;
+        sum = sum.checked_sub(term)?;
+    }
+    Some(sum)
+}
+
+/// Computes `base^(exp_num / exp_den)` where `base` is a fixed-point number at `scale`
+/// in `0..=scale`, rounded up so the result is never below the exact power.
+/// The integer part of the exponent is applied by squaring. For the fractional part
+/// `base` is doubled `n` times into `(scale / 2, scale]`, and both its power and
+/// `(1 / 2)^(n * exp_num / exp_den)` are evaluated with a series that converges there.
+/// Returns `None` for a `base` past `scale` or an `exp_den` of 0.
+pub fn pow_fixed(base: u128, exp_num: u64, exp_den: u64, scale: u128) -> Option<u128> {
+    if base > scale {
+        return None;
+    }
+    let whole = exp_num.checked_div(exp_den)?;
+    let remainder = exp_num.checked_rem(exp_den)?;
+
+    // Exponentiation by squaring for the integer part
+    let mut pow = scale;
+    let mut square = base;
+    let mut n = whole;
+    while n > 0 {
+        if n & 1 == 1 {
+            pow = mul_div_u128(pow, square, scale, true)?;
+        }
+        n >>= 1;
+        if n > 0 {
+            square = mul_div_u128(square, square, scale, true)?;
+        }
+    }
+    if remainder == 0 || pow == 0 {
+        return Some(pow);
+    }
+    if base == 0 {
+        return Some(0);
+    }
+
+    // base = reduced / 2^doublings with reduced in (scale / 2, scale]
+    let half = scale.checked_div(2)?;
+    let mut reduced = base;
+    let mut doublings: u128 = 0;
+    while reduced <= half {
+        reduced = reduced.checked_mul(2)?;
+        doublings = doublings.checked_add(1)?;
+    }
+    pow = mul_div_u128(
+        pow,
+        pow_fraction_up(reduced, remainder, exp_den, scale)?,
+        scale,
+        true,
+    )?;
+
+    // (1 / 2)^(doublings * remainder / exp_den), split into a shift and a fraction
+    let halvings = doublings.checked_mul(remainder.into())?;
+    let shift = halvings.checked_div(exp_den.into())?;
+    let fraction = halvings.checked_rem(exp_den.into())?.to_u64()?;
+    if fraction > 0 {
+        let half_up = scale.checked_sub(half)?;
+        pow = mul_div_u128(
+            pow,
+            pow_fraction_up(half_up, fraction, exp_den, scale)?,
+            scale,
+            true,
+        )?;
+    }
+    if shift >= 128 {
+        return Some(pow.min(1));
+    }
+    let shifted = pow >> shift;
+    if shifted << shift == pow {
+        Some(shifted)
+    } else {
+        shifted.checked_add(1)
+    }
+}
+
+/// Integer square root, rounded down.
+pub fn sqrt_u128(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    // Newton's method
+    let mut x = n;
+    let mut y = x / 2 + 1;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+/// Converts `amount` between token scales, e.g. from a 6 decimal token to 9 decimals.
+/// Downscaling rounds down; an amount downscaled by more than 10^19 is 0.
+pub fn normalize_amount(amount: u64, from_decimals: u32, to_decimals: u32) -> Option<u64> {
+    if to_decimals >= from_decimals {
+        amount.checked_mul(10u64.checked_pow(to_decimals - from_decimals)?)
+    } else {
+        match 10u64.checked_pow(from_decimals - to_decimals) {
+            Some(factor) => Some(amount / factor),
+            None => Some(0),
+        }
+    }
+}
+
+/// Converts `amount` between token scales like [normalize_amount], but scales in u128 so
+/// only the result has to fit in a u64: scale factors past `10^19` don't overflow on their
+/// own. Downscaling rounds down.
+pub fn rescale_u64(amount: u64, from_dec: u32, to_dec: u32) -> Option<u64> {
+    let amount = u128::from(amount);
+    let scaled = if to_dec >= from_dec {
+        match 10u128.checked_pow(to_dec - from_dec) {
+            Some(factor) => amount.checked_mul(factor)?,
+            // only 0 stays in range when scaled up by more than 10^38
+            None if amount == 0 => 0,
+            None => return None,
+        }
+    } else {
+        match 10u128.checked_pow(from_dec - to_dec) {
+            Some(factor) => amount / factor,
+            None => 0,
+        }
+    };
+    scaled.to_u64()
+}
+
+/// Returns `fee`, asserting in debug builds that it doesn't exceed the `amount` it was
+/// taken from, which only happens with a fee numerator above its denominator.
+/// Release builds skip the check.
+#[inline(always)]
+fn debug_check_fee(amount: u64, fee: u64) -> u64 {
+    debug_assert!(
+        fee <= amount,
+        "fee {} exceeds amount {}, check the Fees numerators",
+        fee,
+        amount
+    );
+    fee
+}
+
 /// Calculates fees.
 pub trait FeeCalculator {
     /// Applies the admin trade fee.
     fn admin_trade_fee(&self, fee_amount: u64) -> Option<u64>;
     /// Applies the admin withdraw fee.
     fn admin_withdraw_fee(&self, fee_amount: u64) -> Option<u64>;
-    /// Applies the trade fee.
+    /// Applies the trade fee. Debug builds assert the fee is at most `trade_amount`.
     fn trade_fee(&self, trade_amount: u64) -> Option<u64>;
-    /// Applies the withdraw fee.
+    /// Applies the trade fee rounded down, in favor of the trader. Same as [Self::trade_fee].
+    fn trade_fee_floor(&self, trade_amount: u64) -> Option<u64>;
+    /// Applies the trade fee rounded up, in favor of the pool.
+    /// At most 1 more than [Self::trade_fee_floor].
+    fn trade_fee_ceil(&self, trade_amount: u64) -> Option<u64>;
+    /// Applies the withdraw fee. Debug builds assert the fee is at most `withdraw_amount`.
     fn withdraw_fee(&self, withdraw_amount: u64) -> Option<u64>;
+    /// Trade fee numerator adjusted for `n_coins`, as used by the normalized trade fee.
+    fn adjusted_trade_fee_numerator(&self, n_coins: u8) -> Option<u64>;
     /// Applies the normalized trade fee.
     fn normalized_trade_fee(&self, n_coins: u8, amount: u64) -> Option<u64>;
+    /// Applies the trade fee, then the admin trade fee to the result.
+    fn admin_cut_of_trade(&self, trade_amount: u64) -> Option<u64>;
 }
 
 impl FeeCalculator for Fees {
     /// Apply admin trade fee
     fn admin_trade_fee(&self, fee_amount: u64) -> Option<u64> {
-        mul_div_imbalanced(
+        percent_of(
             fee_amount,
             self.admin_trade_fee_numerator,
             self.admin_trade_fee_denominator,
@@ -61,7 +311,7 @@ impl FeeCalculator for Fees {
 
     /// Apply admin withdraw fee
     fn admin_withdraw_fee(&self, fee_amount: u64) -> Option<u64> {
-        mul_div_imbalanced(
+        percent_of(
             fee_amount,
             self.admin_withdraw_fee_numerator,
             self.admin_withdraw_fee_denominator,
@@ -70,32 +320,59 @@ impl FeeCalculator for Fees {
 
     /// Compute trade fee from amount
     fn trade_fee(&self, trade_amount: u64) -> Option<u64> {
-        mul_div_imbalanced(
+        percent_of(
             trade_amount,
             self.trade_fee_numerator,
             self.trade_fee_denominator,
         )
+        .map(|fee| debug_check_fee(trade_amount, fee))
+    }
+
+    /// Compute trade fee from amount, rounded down
+    fn trade_fee_floor(&self, trade_amount: u64) -> Option<u64> {
+        self.trade_fee(trade_amount)
+    }
+
+    /// Compute trade fee from amount, rounded up
+    fn trade_fee_ceil(&self, trade_amount: u64) -> Option<u64> {
+        let floor = self.trade_fee_floor(trade_amount)?;
+        let remainder = (trade_amount as u128)
+            .checked_mul(self.trade_fee_numerator.into())?
+            .checked_rem(self.trade_fee_denominator.into())?;
+        if remainder == 0 {
+            Some(floor)
+        } else {
+            floor
+                .checked_add(1)
+                .map(|fee| debug_check_fee(trade_amount, fee))
+        }
     }
 
     /// Compute withdraw fee from amount
     fn withdraw_fee(&self, withdraw_amount: u64) -> Option<u64> {
-        mul_div_imbalanced(
+        percent_of(
             withdraw_amount,
             self.withdraw_fee_numerator,
             self.withdraw_fee_denominator,
         )
+        .map(|fee| debug_check_fee(withdraw_amount, fee))
     }
 
-    /// Compute normalized fee for symmetric/asymmetric deposits/withdraws
-    fn normalized_trade_fee(&self, n_coins: u8, amount: u64) -> Option<u64> {
+    /// Compute the trade fee numerator adjusted for the number of coins
+    fn adjusted_trade_fee_numerator(&self, n_coins: u8) -> Option<u64> {
         // adjusted_fee_numerator: uint256 = self.fee * N_COINS / (4 * (N_COINS - 1))
         // The number 4 comes from Curve, originating from some sort of calculus
         // https://github.com/curvefi/curve-contract/blob/e5fb8c0e0bcd2fe2e03634135806c0f36b245511/tests/simulation.py#L124
-        let adjusted_trade_fee_numerator = mul_div(
+        mul_div(
             self.trade_fee_numerator,
             n_coins.into(),
             (n_coins.checked_sub(1)?).checked_mul(4)?.into(),
-        )?;
+        )
+    }
+
+    /// Compute normalized fee for symmetric/asymmetric deposits/withdraws
+    fn normalized_trade_fee(&self, n_coins: u8, amount: u64) -> Option<u64> {
+        let adjusted_trade_fee_numerator = self.adjusted_trade_fee_numerator(n_coins)?;
 
         mul_div(
             amount,
@@ -103,4 +380,455 @@ impl FeeCalculator for Fees {
             self.trade_fee_denominator,
         )
     }
+
+    /// Compute the admin's share of the trade fee charged on a trade amount
+    fn admin_cut_of_trade(&self, trade_amount: u64) -> Option<u64> {
+        self.admin_trade_fee(self.trade_fee(trade_amount)?)
+    }
+}
+
+/// Running total of admin trade fees, kept in u128 so it can be accumulated
+/// over many trades before being swept.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AccruedFees {
+    /// Admin fees accrued so far
+    pub total: u128,
+}
+
+impl AccruedFees {
+    /// Adds the admin cut of `trade_amount` to the total.
+    /// Returns `None` and leaves the total unchanged on overflow.
+    pub fn add_trade(&mut self, fees: &Fees, trade_amount: u64) -> Option<()> {
+        let admin_fee = fees.admin_cut_of_trade(trade_amount)?;
+        self.total = self.total.checked_add(admin_fee.into())?;
+        Some(())
+    }
+}
+
+/// Admin fees waiting to be swept: the part of `vault_balance` not yet counted in
+/// `tracked_reserve`. Returns `None` if the vault holds less than the tracked reserve,
+/// which means the reserve accounting is off and nothing should be swept.
+pub fn pending_admin_fees(vault_balance: u64, tracked_reserve: u64) -> Option<u64> {
+    vault_balance.checked_sub(tracked_reserve)
+}
+
+/// The fee actually charged on `amount_in`, in basis points rounded down, which can differ
+/// from the nominal rate once the fee has been rounded. `amount_in` is the amount the fee was
+/// taken from, e.g. the destination amount for [FeeCalculator::trade_fee].
+/// Returns `None` if `amount_in` is 0.
+pub fn realized_fee_bps(amount_in: u64, fee: u64) -> Option<u64> {
+    mul_div(fee, BPS_DENOMINATOR, amount_in)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn reference_mul_div(a: u64, b: u64, c: u64) -> Option<u64> {
+        (a as u128)
+            .checked_mul(b as u128)?
+            .checked_div(c as u128)?
+            .to_u64()
+    }
+
+    #[test]
+    fn test_uses_wide_path() {
+        let narrow = [(0, 0), (MAX - 1, MAX - 1), (MAX - 1, 0), (0, MAX - 1)];
+        let wide = [(MAX, 0), (0, MAX), (MAX, MAX), (u64::MAX, 1)];
+        for (a, b) in narrow.iter() {
+            assert!(!mul_div_uses_wide_path(*a, *b));
+            // the 64 bit product fits
+            assert!(a.checked_mul(*b).is_some());
+            assert_eq!(mul_div(*a, *b, 3), reference_mul_div(*a, *b, 3));
+        }
+        for (a, b) in wide.iter() {
+            assert!(mul_div_uses_wide_path(*a, *b));
+            assert_eq!(mul_div(*a, *b, MAX), reference_mul_div(*a, *b, MAX));
+        }
+
+        let narrow = [(0, 0), (MAX_BIG - 1, MAX_SMALL - 1), (MAX_BIG - 1, 0)];
+        let wide = [(MAX_BIG, 0), (0, MAX_SMALL), (MAX_BIG, MAX_SMALL), (1, MAX)];
+        for (a, b) in narrow.iter() {
+            assert!(!mul_div_imbalanced_uses_wide_path(*a, *b));
+            assert!(a.checked_mul(*b).is_some());
+            assert_eq!(mul_div_imbalanced(*a, *b, 3), reference_mul_div(*a, *b, 3));
+        }
+        for (a, b) in wide.iter() {
+            assert!(mul_div_imbalanced_uses_wide_path(*a, *b));
+            assert_eq!(
+                mul_div_imbalanced(*a, *b, MAX_SMALL),
+                reference_mul_div(*a, *b, MAX_SMALL)
+            );
+        }
+    }
+
+    #[test]
+    fn test_mul_div_fast_path_bounds() {
+        // largest operands still taking the 64 bit path
+        assert!((MAX - 1).checked_mul(MAX - 1).is_some());
+        assert!((MAX_BIG - 1).checked_mul(MAX_SMALL - 1).is_some());
+        // MAX * MAX is exactly 2^64, so it must go through u128
+        assert!(MAX.checked_mul(MAX).is_none());
+        assert!(MAX_BIG.checked_mul(MAX_SMALL).is_none());
+
+        assert_eq!(mul_div(MAX, MAX, MAX), Some(MAX));
+        assert_eq!(mul_div(MAX - 1, MAX - 1, 1), Some((MAX - 1) * (MAX - 1)));
+        assert_eq!(
+            mul_div_imbalanced(MAX_BIG, MAX_SMALL, MAX_SMALL),
+            Some(MAX_BIG)
+        );
+        assert_eq!(
+            mul_div_imbalanced(MAX_BIG - 1, MAX_SMALL - 1, 1),
+            Some((MAX_BIG - 1) * (MAX_SMALL - 1))
+        );
+    }
+
+    #[test]
+    fn test_mul_div_checked() {
+        assert_eq!(mul_div_checked(6, 4, 3), Ok(8));
+        assert_eq!(mul_div_checked(6, 4, 0), Err(MathError::DivideByZero));
+        assert_eq!(mul_div_checked(u64::MAX, 2, 1), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn test_percent_of() {
+        assert_eq!(percent_of(1_000_000, 30, 10_000), Some(3_000));
+        assert_eq!(percent_of(1_000_000, 1, 3), Some(333_333));
+        assert_eq!(percent_of(1_000, 3, 2), Some(1_500));
+        assert_eq!(percent_of(1_000, 1, 0), None);
+        assert_eq!(percent_of(u64::MAX, 2, 1), None);
+
+        let configs = [
+            Fees::from_bps(30, 5_000, 10, 2_500),
+            Fees::from_bps(4, 0, 0, 10_000),
+            Fees::from_bps(10_000, 10_000, 10_000, 10_000),
+        ];
+        for fees in configs.iter() {
+            for amount in [0, 1, 9_999, 1_000_000, MAX_BIG, u64::MAX].iter() {
+                let amount = *amount;
+                assert_eq!(
+                    fees.trade_fee(amount),
+                    mul_div_imbalanced(
+                        amount,
+                        fees.trade_fee_numerator,
+                        fees.trade_fee_denominator
+                    )
+                );
+                assert_eq!(
+                    fees.admin_trade_fee(amount),
+                    mul_div_imbalanced(
+                        amount,
+                        fees.admin_trade_fee_numerator,
+                        fees.admin_trade_fee_denominator
+                    )
+                );
+                assert_eq!(
+                    fees.withdraw_fee(amount),
+                    mul_div_imbalanced(
+                        amount,
+                        fees.withdraw_fee_numerator,
+                        fees.withdraw_fee_denominator
+                    )
+                );
+                assert_eq!(
+                    fees.admin_withdraw_fee(amount),
+                    mul_div_imbalanced(
+                        amount,
+                        fees.admin_withdraw_fee_numerator,
+                        fees.admin_withdraw_fee_denominator
+                    )
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_sum_checked() {
+        assert_eq!(sum_checked(&[1_000, 2_000, 3]), Some(3_003));
+        assert_eq!(sum_checked(&[]), Some(0));
+        assert_eq!(sum_checked(&[u64::MAX, 0]), Some(u64::MAX));
+        assert_eq!(sum_checked(&[u64::MAX, 1]), None);
+        assert_eq!(sum_checked(&[u64::MAX / 2, u64::MAX / 2, 2]), None);
+    }
+
+    #[test]
+    fn test_trade_fee_rounding() {
+        // 30 bps of 1_001 is 3.003
+        let fees = Fees::from_bps(30, 0, 0, 0);
+        assert_eq!(fees.trade_fee_floor(1_001), Some(3));
+        assert_eq!(fees.trade_fee_ceil(1_001), Some(4));
+        assert_eq!(fees.trade_fee_floor(1_001), fees.trade_fee(1_001));
+        for amount in [0, 1, 333, 1_001, 10_000, 999_999_999, u64::MAX].iter() {
+            let floor = fees.trade_fee_floor(*amount).unwrap();
+            let ceil = fees.trade_fee_ceil(*amount).unwrap();
+            // floor <= amount * 30 / 10_000 <= ceil
+            let exact = (*amount as u128) * 30;
+            assert!((floor as u128) * 10_000 <= exact);
+            assert!((ceil as u128) * 10_000 >= exact);
+            assert!(ceil - floor <= 1);
+            assert_eq!(ceil == floor, (floor as u128) * 10_000 == exact);
+        }
+
+        let no_fee = Fees::from_bps(0, 0, 0, 0);
+        assert_eq!(no_fee.trade_fee_ceil(1_001), Some(0));
+        let full_fee = Fees::from_bps(10_000, 0, 0, 0);
+        assert_eq!(full_fee.trade_fee_ceil(u64::MAX), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_admin_cut_of_trade() {
+        let mut fees = Fees {
+            admin_trade_fee_numerator: 1,
+            admin_trade_fee_denominator: 2,
+            admin_withdraw_fee_numerator: 0,
+            admin_withdraw_fee_denominator: 1,
+            trade_fee_numerator: 4,
+            trade_fee_denominator: 1_000,
+            withdraw_fee_numerator: 0,
+            withdraw_fee_denominator: 1,
+        };
+        assert_eq!(fees.admin_cut_of_trade(1_000_000), Some(2_000));
+        for trade_amount in [0, 1, 999, 1_000_000, u64::MAX] {
+            let admin_cut = fees.admin_cut_of_trade(trade_amount).unwrap();
+            assert!(admin_cut <= fees.trade_fee(trade_amount).unwrap());
+        }
+
+        fees.trade_fee_numerator = 0;
+        assert_eq!(fees.trade_fee(1_000_000), Some(0));
+        assert_eq!(fees.admin_cut_of_trade(1_000_000), Some(0));
+        fees.trade_fee_numerator = 4;
+        fees.admin_trade_fee_numerator = 0;
+        assert_eq!(fees.admin_cut_of_trade(1_000_000), Some(0));
+    }
+
+    #[test]
+    fn test_adjusted_trade_fee_numerator() {
+        let fees = Fees {
+            admin_trade_fee_numerator: 0,
+            admin_trade_fee_denominator: 1,
+            admin_withdraw_fee_numerator: 0,
+            admin_withdraw_fee_denominator: 1,
+            trade_fee_numerator: 40,
+            trade_fee_denominator: 10_000,
+            withdraw_fee_numerator: 0,
+            withdraw_fee_denominator: 1,
+        };
+        // 40 * 2 / (4 * 1)
+        assert_eq!(fees.adjusted_trade_fee_numerator(2), Some(20));
+        assert_eq!(fees.normalized_trade_fee(2, 1_000_000), Some(2_000));
+        // 40 * 3 / (4 * 2)
+        assert_eq!(fees.adjusted_trade_fee_numerator(3), Some(15));
+        assert_eq!(fees.normalized_trade_fee(3, 1_000_000), Some(1_500));
+        // a single coin pool has no adjustment
+        assert_eq!(fees.adjusted_trade_fee_numerator(1), None);
+        assert_eq!(fees.adjusted_trade_fee_numerator(0), None);
+    }
+
+    #[test]
+    fn test_fees_from_bps() {
+        let fees = Fees::from_bps(30, 5_000, 10, 2_500);
+        assert_eq!(fees.trade_fee(10_000), Some(30));
+        assert_eq!(fees.admin_trade_fee(30), Some(15));
+        assert_eq!(fees.withdraw_fee(10_000), Some(10));
+        assert_eq!(fees.admin_withdraw_fee(100), Some(25));
+    }
+
+    #[test]
+    fn test_debug_check_fee() {
+        let fees = Fees::from_bps(10_000, 0, 30, 0);
+        for amount in [0, 1, 9_999, u64::MAX].iter() {
+            assert_eq!(fees.trade_fee(*amount), Some(*amount));
+            assert!(fees.withdraw_fee(*amount).unwrap() <= *amount);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "exceeds amount")]
+    fn test_debug_check_fee_misconfigured() {
+        let fees = Fees::from_bps(10_001, 0, 0, 0);
+        fees.trade_fee(10_000);
+    }
+
+    #[test]
+    fn test_accrued_fees() {
+        let fees = Fees {
+            admin_trade_fee_numerator: 1,
+            admin_trade_fee_denominator: 2,
+            admin_withdraw_fee_numerator: 0,
+            admin_withdraw_fee_denominator: 1,
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1,
+            withdraw_fee_numerator: 0,
+            withdraw_fee_denominator: 1,
+        };
+        let cut = fees.admin_cut_of_trade(u64::MAX).unwrap();
+
+        // far more than fits in a u64
+        let mut accrued = AccruedFees::default();
+        for _ in 0..1_000 {
+            accrued.add_trade(&fees, u64::MAX).unwrap();
+        }
+        assert_eq!(accrued.total, cut as u128 * 1_000);
+
+        let mut accrued = AccruedFees {
+            total: u128::MAX - cut as u128,
+        };
+        assert_eq!(accrued.add_trade(&fees, u64::MAX), Some(()));
+        assert_eq!(accrued.total, u128::MAX);
+        assert_eq!(accrued.add_trade(&fees, 0), Some(()));
+        assert_eq!(accrued.add_trade(&fees, 2), None);
+        assert_eq!(accrued.total, u128::MAX);
+    }
+
+    #[test]
+    fn test_pending_admin_fees() {
+        assert_eq!(pending_admin_fees(1_000_250, 1_000_000), Some(250));
+        assert_eq!(pending_admin_fees(1_000_000, 1_000_000), Some(0));
+        assert_eq!(pending_admin_fees(u64::MAX, 0), Some(u64::MAX));
+        assert_eq!(pending_admin_fees(999_999, 1_000_000), None);
+    }
+
+    #[test]
+    fn test_realized_fee_bps() {
+        let fees = Fees::from_bps(30, 0, 0, 0);
+        let amount = 1_000_000_000;
+        let fee = fees.trade_fee(amount).unwrap();
+        assert_eq!(realized_fee_bps(amount, fee), Some(30));
+        // rounding the fee of a small trade moves the realized rate
+        assert_eq!(
+            realized_fee_bps(1_001, fees.trade_fee(1_001).unwrap()),
+            Some(29)
+        );
+        assert_eq!(
+            realized_fee_bps(1_001, fees.trade_fee_ceil(1_001).unwrap()),
+            Some(39)
+        );
+        assert_eq!(
+            realized_fee_bps(10, fees.trade_fee_floor(10).unwrap()),
+            Some(0)
+        );
+
+        assert_eq!(realized_fee_bps(u64::MAX, u64::MAX), Some(10_000));
+        assert_eq!(realized_fee_bps(0, 0), None);
+        assert_eq!(realized_fee_bps(0, 3), None);
+    }
+
+    #[test]
+    fn test_sqrt_u128() {
+        assert_eq!(sqrt_u128(0), 0);
+        assert_eq!(sqrt_u128(1), 1);
+        assert_eq!(sqrt_u128(15), 3);
+        assert_eq!(sqrt_u128(16), 4);
+        assert_eq!(sqrt_u128(u128::MAX), u64::MAX.into());
+    }
+
+    #[test]
+    fn test_normalize_amount() {
+        // 1.5 USDC (6 decimals) in 9 decimals
+        assert_eq!(normalize_amount(1_500_000, 6, 9), Some(1_500_000_000));
+        // 9 -> 6 truncates the last three digits
+        assert_eq!(normalize_amount(1_500_000_999, 9, 6), Some(1_500_000));
+        assert_eq!(normalize_amount(999, 9, 6), Some(0));
+        assert_eq!(normalize_amount(42, 6, 6), Some(42));
+
+        assert_eq!(
+            normalize_amount(u64::MAX / 1_000, 6, 9),
+            Some(u64::MAX / 1_000 * 1_000)
+        );
+        assert_eq!(normalize_amount(u64::MAX / 1_000 + 1, 6, 9), None);
+        assert_eq!(normalize_amount(1, 0, 20), None);
+        assert_eq!(normalize_amount(u64::MAX, 20, 0), Some(0));
+    }
+
+    #[test]
+    fn test_rescale_u64() {
+        assert_eq!(rescale_u64(1_500_000, 6, 9), Some(1_500_000_000));
+        assert_eq!(rescale_u64(1_500_000_999, 9, 6), Some(1_500_000));
+        assert_eq!(rescale_u64(42, 6, 6), Some(42));
+
+        // the largest upscale still in range
+        assert_eq!(rescale_u64(1, 0, 19), Some(10_000_000_000_000_000_000));
+        assert_eq!(rescale_u64(2, 0, 19), None);
+        assert_eq!(
+            rescale_u64(u64::MAX / 1_000, 6, 9),
+            Some(u64::MAX / 1_000 * 1_000)
+        );
+        assert_eq!(rescale_u64(u64::MAX / 1_000 + 1, 6, 9), None);
+        // factors that overflow a u64 on their own
+        assert_eq!(rescale_u64(0, 0, 20), Some(0));
+        assert_eq!(normalize_amount(0, 0, 20), None);
+        assert_eq!(rescale_u64(0, 0, 60), Some(0));
+        assert_eq!(rescale_u64(1, 0, 20), None);
+        assert_eq!(rescale_u64(1, 0, 60), None);
+        // downscaling by factors past 10^19
+        assert_eq!(rescale_u64(u64::MAX, 30, 0), Some(0));
+        assert_eq!(rescale_u64(u64::MAX, 20, 1), Some(1));
+        assert_eq!(rescale_u64(u64::MAX, 60, 0), Some(0));
+    }
+
+    #[test]
+    fn test_pow_fixed() {
+        let scale = 1_000_000_000_000;
+        let base = 810_000_000_000;
+        assert_eq!(pow_fixed(base, 1, 1, scale), Some(base));
+        assert_eq!(pow_fixed(base, 7, 7, scale), Some(base));
+        assert_eq!(pow_fixed(base, 2, 1, scale), Some(656_100_000_000));
+
+        let root = pow_fixed(base, 1, 2, scale).unwrap();
+        let expected = sqrt_u128(base * scale);
+        assert_eq!(expected, 900_000_000_000);
+        assert!(root >= expected && root - expected < 1_000);
+
+        // far from 1 the base is halved into range, and still rounded up
+        let root = pow_fixed(scale / 1_000, 1, 2, scale).unwrap();
+        let expected = sqrt_u128(scale / 1_000 * scale);
+        assert!(root > expected && root - expected < 1_000);
+        let root = pow_fixed(1, 1, 2, scale).unwrap();
+        assert!(root >= 1_000_000 && root - 1_000_000 < 1_000);
+        assert_eq!(pow_fixed(0, 1, 2, scale), Some(0));
+        assert_eq!(pow_fixed(0, 0, 2, scale), Some(scale));
+        // outside the range of the series
+        assert_eq!(pow_fixed(scale + 1, 1, 2, scale), None);
+        assert_eq!(pow_fixed(base, 1, 0, scale), None);
+    }
+
+    proptest! {
+        #[test]
+        fn test_pow_fixed_rounds_up(base in 0..=1u128 << 60) {
+            let scale = 1u128 << 60;
+            let pow4 = |n: u128| U256::from(n).pow(U256::from(4));
+            let cube = U256::from(scale).pow(U256::from(3));
+
+            // p >= (base / scale)^4 exactly when p * scale^3 >= base^4
+            let p = pow_fixed(base, 4, 1, scale).unwrap();
+            prop_assert!(U256::from(p) * cube >= pow4(base));
+            prop_assert!(p < 8 || U256::from(p - 8) * cube < pow4(base));
+
+            // p >= (base / scale)^(1/4) exactly when p^4 >= base * scale^3
+            let p = pow_fixed(base, 1, 4, scale).unwrap();
+            prop_assert!(pow4(p) >= U256::from(base) * cube);
+            prop_assert!(p < 1_000 || pow4(p - 1_000) < U256::from(base) * cube);
+        }
+
+        #[test]
+        fn test_mul_div(
+            a in prop_oneof![0..=MAX, any::<u64>()],
+            b in prop_oneof![0..=MAX, any::<u64>()],
+            c in any::<u64>(),
+        ) {
+            prop_assert_eq!(mul_div(a, b, c), reference_mul_div(a, b, c));
+        }
+
+        #[test]
+        fn test_mul_div_imbalanced(
+            a in prop_oneof![0..=MAX_BIG, any::<u64>()],
+            b in prop_oneof![0..=MAX_SMALL, any::<u64>()],
+            c in any::<u64>(),
+        ) {
+            prop_assert_eq!(mul_div_imbalanced(a, b, c), reference_mul_div(a, b, c));
+        }
+    }
 }
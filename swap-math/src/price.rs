@@ -2,8 +2,13 @@
 
 use num_traits::ToPrimitive;
 
+use crate::{curve::StableSwap, decimal::Decimal};
+
 const PRECISION: u128 = 1_000_000_000_000;
 
+/// Seconds in a 365 day year.
+pub const SECONDS_PER_YEAR: u128 = 365 * 24 * 60 * 60;
+
 /// An LP token's price.
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Price {
@@ -49,3 +54,221 @@ impl Price {
             .to_u64()
     }
 }
+
+/// Calculates the virtual price of a stable pool's LP token, i.e. the
+/// invariant (D) per LP token, as a [Decimal] with `result_decimals` places.
+pub fn virtual_price(
+    reserves: &[u64],
+    lp_supply: u64,
+    amp: u64,
+    result_decimals: u32,
+) -> Option<Decimal> {
+    let (amount_a, amount_b) = match reserves {
+        [amount_a, amount_b] => (*amount_a, *amount_b),
+        _ => return None,
+    };
+    let d = StableSwap::new(amp, amp, 0, 0, 0).compute_d(amount_a, amount_b)?;
+    let value = d
+        .to_u128()?
+        .checked_mul(10u128.checked_pow(result_decimals)?)?
+        .checked_div(lp_supply.into())?;
+    Some(Decimal::new(value, result_decimals))
+}
+
+/// Annualizes the growth of a pool's [virtual_price] from `virtual_price_start` to
+/// `virtual_price_end` over `elapsed_secs`, without compounding, as the fraction earned
+/// per year (`0.05` is 5%) with the decimals of the more precise price, rounded down.
+/// Returns `None` if no time elapsed, the start price is 0 or the virtual price fell.
+pub fn implied_apr(
+    virtual_price_start: Decimal,
+    virtual_price_end: Decimal,
+    elapsed_secs: i64,
+) -> Option<Decimal> {
+    if elapsed_secs <= 0 {
+        return None;
+    }
+    let decimals = virtual_price_start.decimals.max(virtual_price_end.decimals);
+    let rescale = |price: Decimal| {
+        price
+            .value
+            .checked_mul(10u128.checked_pow(decimals.checked_sub(price.decimals)?)?)
+    };
+    let start = rescale(virtual_price_start)?;
+    let growth = rescale(virtual_price_end)?.checked_sub(start)?;
+    let value = growth
+        .checked_mul(10u128.checked_pow(decimals)?)?
+        .checked_mul(SECONDS_PER_YEAR)?
+        .checked_div(start.checked_mul(elapsed_secs as u128)?)?;
+    Some(Decimal::new(value, decimals))
+}
+
+/// Calculates the total value locked in a pool, `sum(reserve_i * price_i)`, with
+/// the decimals of the most precise price. Reserves are in the tokens' smallest units.
+/// Returns `None` if there isn't exactly one price per reserve or the sum overflows.
+pub fn total_value(reserves: &[u64], prices: &[Decimal]) -> Option<Decimal> {
+    if reserves.len() != prices.len() {
+        return None;
+    }
+    let decimals = prices.iter().map(|price| price.decimals).max().unwrap_or(0);
+    let mut value: u128 = 0;
+    for (reserve, price) in reserves.iter().zip(prices) {
+        let scale = 10u128.checked_pow(decimals.checked_sub(price.decimals)?)?;
+        value = price
+            .value
+            .checked_mul(scale)?
+            .checked_mul((*reserve).into())?
+            .checked_add(value)?;
+    }
+    Some(Decimal::new(value, decimals))
+}
+
+/// Values `lp_tokens` out of `lp_supply` in a single asset: the proportional share of
+/// each reserve is valued at `prices`, as in [total_value], and the total converted to
+/// the smallest units of the asset at `asset_index`, rounded down.
+/// Returns `None` if `asset_index` is out of range or its price is 0.
+pub fn lp_value_in_asset(
+    lp_tokens: u64,
+    lp_supply: u64,
+    reserves: &[u64],
+    asset_index: usize,
+    prices: &[Decimal],
+) -> Option<u64> {
+    let asset_price = prices.get(asset_index)?;
+    if reserves.len() != prices.len() {
+        return None;
+    }
+    let decimals = prices.iter().map(|price| price.decimals).max().unwrap_or(0);
+    let mut value: u128 = 0;
+    for (reserve, price) in reserves.iter().zip(prices) {
+        let share = (*reserve as u128)
+            .checked_mul(lp_tokens.into())?
+            .checked_div(lp_supply.into())?;
+        let scale = 10u128.checked_pow(decimals.checked_sub(price.decimals)?)?;
+        value = price
+            .value
+            .checked_mul(scale)?
+            .checked_mul(share)?
+            .checked_add(value)?;
+    }
+    let scale = 10u128.checked_pow(decimals.checked_sub(asset_price.decimals)?)?;
+    value
+        .checked_div(asset_price.value.checked_mul(scale)?)?
+        .to_u64()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_price() {
+        let reserve = 1_000_000_000;
+        let lp_supply = 2 * reserve;
+        let genesis = virtual_price(&[reserve, reserve], lp_supply, 100, 6).unwrap();
+        assert!(genesis.value >= 999_999 && genesis.value <= 1_000_001);
+
+        // fees accrue into the reserves while supply stays the same
+        let grown = virtual_price(
+            &[reserve + 50_000_000, reserve + 30_000_000],
+            lp_supply,
+            100,
+            6,
+        )
+        .unwrap();
+        assert!(grown.value > genesis.value);
+        assert!(grown.value > 1_039_000 && grown.value < 1_041_000);
+
+        assert_eq!(virtual_price(&[reserve], lp_supply, 100, 6), None);
+        assert_eq!(virtual_price(&[reserve, reserve], 0, 100, 6), None);
+    }
+
+    #[test]
+    fn test_implied_apr() {
+        let start = Decimal::new(1_000_000, 6);
+        // 1% over a quarter of a year is 4% a year
+        let quarter = (SECONDS_PER_YEAR / 4) as i64;
+        assert_eq!(
+            implied_apr(start, Decimal::new(1_010_000, 6), quarter),
+            Some(Decimal::new(40_000, 6))
+        );
+        // 5% over a full year, with a more precise end price
+        let year = SECONDS_PER_YEAR as i64;
+        assert_eq!(
+            implied_apr(start, Decimal::new(1_050_000_000, 9), year),
+            Some(Decimal::new(50_000_000, 9))
+        );
+        assert_eq!(implied_apr(start, start, year), Some(Decimal::new(0, 6)));
+
+        assert_eq!(implied_apr(start, Decimal::new(1_010_000, 6), 0), None);
+        assert_eq!(implied_apr(start, Decimal::new(1_010_000, 6), -1), None);
+        assert_eq!(implied_apr(start, Decimal::new(990_000, 6), year), None);
+        assert_eq!(
+            implied_apr(Decimal::new(0, 6), Decimal::new(1_010_000, 6), year),
+            None
+        );
+    }
+
+    #[test]
+    fn test_total_value() {
+        // 1_000 tokens at 1.50 and 20 tokens at 41.234567
+        let prices = [Decimal::new(150, 2), Decimal::new(41_234_567, 6)];
+        let value = total_value(&[1_000, 20], &prices).unwrap();
+        assert_eq!(value, Decimal::new(2_324_691_340, 6));
+        assert_eq!(value.to_string(), "2324.691340");
+
+        assert_eq!(total_value(&[], &[]), Some(Decimal::new(0, 0)));
+        assert_eq!(total_value(&[0, 0], &prices), Some(Decimal::new(0, 6)));
+
+        // one price per reserve
+        assert_eq!(total_value(&[1_000], &prices), None);
+        assert_eq!(total_value(&[1_000, 20, 5], &prices), None);
+
+        assert_eq!(
+            total_value(
+                &[u64::MAX, u64::MAX],
+                &[Decimal::new(u64::MAX.into(), 0); 2]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_lp_value_in_asset() {
+        let one = Decimal::new(100, 2);
+        // 10% of a balanced pool is worth 10% of both reserves in either asset
+        let reserves = [1_000_000, 1_000_000];
+        assert_eq!(
+            lp_value_in_asset(100, 1_000, &reserves, 0, &[one, one]),
+            Some(200_000)
+        );
+        assert_eq!(
+            lp_value_in_asset(100, 1_000, &reserves, 1, &[one, one]),
+            Some(200_000)
+        );
+
+        // token A at 2.000 is worth twice token B
+        let prices = [Decimal::new(2_000, 3), one];
+        let reserves = [500_000, 1_000_000];
+        assert_eq!(
+            lp_value_in_asset(100, 1_000, &reserves, 0, &prices),
+            Some(100_000)
+        );
+        assert_eq!(
+            lp_value_in_asset(100, 1_000, &reserves, 1, &prices),
+            Some(200_000)
+        );
+        assert_eq!(
+            lp_value_in_asset(1_000, 1_000, &reserves, 0, &prices),
+            Some(1_000_000)
+        );
+
+        assert_eq!(lp_value_in_asset(100, 1_000, &reserves, 2, &prices), None);
+        assert_eq!(lp_value_in_asset(100, 1_000, &reserves, 0, &[one]), None);
+        assert_eq!(lp_value_in_asset(100, 0, &reserves, 0, &prices), None);
+        assert_eq!(
+            lp_value_in_asset(100, 1_000, &reserves, 0, &[Decimal::new(0, 2), one]),
+            None
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Weighted (Balancer-style) pool calculations
+
+use num_traits::ToPrimitive;
+
+use crate::math::pow_fixed;
+
+/// Fixed-point scale used for the weighted math. At `2^64` one unit of the scale is
+/// worth less than one token of any u64 reserve, so rounding the power up barely
+/// lowers the output, however large the reserves.
+const PRECISION: u128 = 1 << 64;
+
+/// Computes the amount of destination token received for `amount_in`
+/// in a weighted pool, before any fees are applied.
+/// Equation:
+/// dy = y * (1 - (x / (x + dx)) ^ (w_in / w_out))
+///
+/// The output is rounded down, never paying out more than the exact curve.
+pub fn weighted_out(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    weight_in: u64,
+    weight_out: u64,
+) -> Option<u64> {
+    // Round the base and its power up so the output is rounded down
+    let denominator = (reserve_in as u128).checked_add(amount_in.into())?;
+    let numerator = (reserve_in as u128).checked_mul(PRECISION)?;
+    let mut base = numerator.checked_div(denominator)?;
+    if numerator.checked_rem(denominator)? != 0 {
+        base = base.checked_add(1)?;
+    }
+    let power = pow_fixed(base, weight_in, weight_out, PRECISION)?;
+    (reserve_out as u128)
+        .checked_mul(PRECISION.checked_sub(power)?)?
+        .checked_div(PRECISION)?
+        .to_u64()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::{bn::U256, constant_product::constant_product_out};
+    use proptest::prelude::*;
+
+    const RESERVE: u64 = 1_000_000_000;
+    /// Keeps the fourth powers of the exact checks within a [U256].
+    const MAX_EXACT: u64 = 1 << 40;
+
+    fn pow4(n: u64) -> U256 {
+        U256::from(n).pow(U256::from(4))
+    }
+
+    #[test]
+    fn test_weighted_out() {
+        for amount_in in [1_000, 1_000_000, 500_000_000] {
+            let expected = constant_product_out(amount_in, RESERVE, RESERVE).unwrap();
+            let out = weighted_out(amount_in, RESERVE, RESERVE, 50, 50).unwrap();
+            assert!(out <= expected && expected - out <= 1);
+        }
+
+        // 80/20 pool: the heavy side buys more of the light side
+        let amount_in = 10_000_000;
+        let heavy_in = weighted_out(amount_in, RESERVE, RESERVE, 80, 20).unwrap();
+        let light_in = weighted_out(amount_in, RESERVE, RESERVE, 20, 80).unwrap();
+        let balanced = constant_product_out(amount_in, RESERVE, RESERVE).unwrap();
+        assert!(heavy_in > balanced);
+        assert!(light_in < balanced);
+        assert!(light_in > 0);
+
+        // trades far past the reserves still converge
+        let out = weighted_out(1_000 * RESERVE, RESERVE, RESERVE, 20, 80).unwrap();
+        assert!(out > RESERVE * 8 / 10 && out < RESERVE);
+        // (1 / 2^64)^(1/4) is 2^-16
+        assert_eq!(
+            weighted_out(u64::MAX, 1, RESERVE, 20, 80),
+            Some(RESERVE - RESERVE / (1 << 16) - 1)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_weighted_out_80_20_rounds_down(
+            amount_in in 0..MAX_EXACT,
+            reserve_in in 1..MAX_EXACT,
+            reserve_out in 1..MAX_EXACT,
+        ) {
+            let new_reserve_in = reserve_in + amount_in;
+
+            // heavy side in: dy = y * ((x + dx)^4 - x^4) / (x + dx)^4
+            let out = weighted_out(amount_in, reserve_in, reserve_out, 80, 20).unwrap();
+            let exact = (U256::from(reserve_out) * (pow4(new_reserve_in) - pow4(reserve_in))
+                / pow4(new_reserve_in))
+            .to_u64()
+            .unwrap();
+            prop_assert!(out <= exact && exact - out <= 1);
+
+            // light side in: dy <= y * (1 - (x / (x + dx))^(1/4)) exactly
+            // when x * y^4 <= (x + dx) * (y - dy)^4
+            let below_exact = |out: u64| {
+                U256::from(reserve_in) * pow4(reserve_out)
+                    <= U256::from(new_reserve_in) * pow4(reserve_out - out)
+            };
+            let out = weighted_out(amount_in, reserve_in, reserve_out, 20, 80).unwrap();
+            prop_assert!(below_exact(out));
+            prop_assert!(out + 2 > reserve_out || !below_exact(out + 2));
+        }
+    }
+}